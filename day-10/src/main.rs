@@ -7,38 +7,38 @@ static INPUT: &str = include_str!("../input.txt");
 type Error = Box<dyn std::error::Error>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Grids with more cells than this are too large to print
+const MAX_PRINTABLE_CELLS: i64 = 100_000;
+
 fn main() -> Result<()> {
     let points = points()?;
+    let grid = Grid(points);
 
-    let mut grid = Grid(points);
-    let mut min_grid = grid.clone();
-
-    let max_area = grid.area()?;
-    let mut min_area = max_area;
-    let mut step = 0;
-    let mut min_step = step;
-
-
-    loop {
-        let current_area = grid.area()?;
-
-        if current_area < min_area {
-            min_area = current_area;
-            min_step = step;
-            min_grid = grid.clone();
+    let mut at = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--at" => at = Some(args.next().ok_or("Missing time")?.parse()?),
+            _ => return Err(format!("Unknown argument {}", arg).into()),
         }
+    }
 
-        if current_area > max_area {
-            break;
+    let time = match at {
+        Some(time) => time,
+        None => {
+            let max_area = grid.area()?;
+            let min_step = grid.convergence_time()?;
+            println!("Max area is {}", max_area);
+            println!("Min area is {} at step {}", grid.at(min_step)?.area()?, min_step);
+            min_step
         }
+    };
 
-        grid.step();
-        step += 1;
+    let grid = grid.at(time)?;
+    match grid.cells()? {
+        Some(cells) if cells <= MAX_PRINTABLE_CELLS => println!("{}", grid),
+        _ => return Err(format!("The grid at step {} is too large to print", time).into()),
     }
-    println!("Total of {} steps", step);
-    println!("Max area is {}", max_area);
-    println!("Min area is {} at step {}", min_area, min_step);
-    println!("{}", min_grid);
 
     Ok(())
 }
@@ -52,6 +52,15 @@ impl Grid {
         Ok((x1-x0) * (y1-y0))
     }
 
+    /// How many cells printing the grid takes, which is never zero even
+    /// when the points are in a line, or `None` if that doesn't fit in
+    /// an `i64`
+    fn cells(&self) -> Result<Option<i64>> {
+        let ((x0, x1), (y0, y1)) = self.bounds()?;
+        let side = |lo: i64, hi: i64| hi.checked_sub(lo)?.checked_add(1);
+        Ok(side(x0, x1).zip(side(y0, y1)).and_then(|(w, h)| w.checked_mul(h)))
+    }
+
     fn bounds(&self) -> Result<((i64, i64), (i64, i64))> {
         let x = self.0.iter().map(|pt| pt.x).minmax().into_option().ok_or("No points")?;
         let y = self.0.iter().map(|pt| pt.y).minmax().into_option().ok_or("No points")?;
@@ -59,11 +68,81 @@ impl Grid {
         Ok((x, y))
     }
 
-    fn step(&mut self) {
-        for pt in &mut self.0 {
-            pt.step();
+    /// Moves every point `dt` seconds, backwards when negative. If any
+    /// point would move out of range, none of them move.
+    fn step_by(&mut self, dt: i64) -> Result<()> {
+        let mut points = self.0.clone();
+        for pt in &mut points {
+            pt.step_by(dt)?;
+        }
+        self.0 = points;
+        Ok(())
+    }
+
+    /// The grid `dt` seconds from now
+    fn at(&self, dt: i64) -> Result<Grid> {
+        let mut grid = self.clone();
+        grid.step_by(dt)?;
+        Ok(grid)
+    }
+
+    /// The time, no earlier than now, when the points are closest
+    /// together.
+    ///
+    /// The width and height of the bounding box are each the distance
+    /// between the furthest apart points, which is convex over time, so
+    /// each can be minimized by a ternary search. Before both of those
+    /// times the area only shrinks and after both it only grows, so the
+    /// smallest area is between them.
+    fn convergence_time(&self) -> Result<i64> {
+        if self.0.is_empty() {
+            return Err("No points".into());
+        }
+
+        let width = |t| extent(self.0.iter().map(|pt| (pt.x, pt.dx)), t);
+        let height = |t| extent(self.0.iter().map(|pt| (pt.y, pt.dy)), t);
+
+        let tw = convex_minimum(width);
+        let th = convex_minimum(height);
+
+        let time = (tw.min(th)..=tw.max(th))
+            .min_by_key(|&t| width(t) * height(t))
+            .expect("Range is never empty");
+
+        Ok(time)
+    }
+}
+
+/// The distance between the furthest apart positions at time `t`
+fn extent(positions: impl Iterator<Item = (i64, i64)>, t: i64) -> i64 {
+    let (min, max) = positions
+        .map(|(p, dp)| p + dp * t)
+        .minmax()
+        .into_option()
+        .expect("No points");
+    max - min
+}
+
+/// The earliest non-negative time minimizing a convex function
+fn convex_minimum(f: impl Fn(i64) -> i64) -> i64 {
+    // Once a later time is no better, the minimum can't be past it
+    let mut hi = 1;
+    while f(hi) < f(hi / 2) {
+        hi *= 2;
+    }
+
+    let mut lo = 0;
+    while hi - lo > 2 {
+        let m1 = lo + (hi - lo) / 3;
+        let m2 = hi - (hi - lo) / 3;
+        if f(m1) <= f(m2) {
+            hi = m2;
+        } else {
+            lo = m1;
         }
     }
+
+    (lo..=hi).min_by_key(|&t| f(t)).expect("Range is never empty")
 }
 
 use std::fmt;
@@ -97,9 +176,17 @@ struct Point {
 }
 
 impl Point {
-    fn step(&mut self) {
-        self.x += self.dx;
-        self.y += self.dy;
+    fn step_by(&mut self, dt: i64) -> Result<()> {
+        let x = self.dx.checked_mul(dt).and_then(|d| self.x.checked_add(d));
+        let y = self.dy.checked_mul(dt).and_then(|d| self.y.checked_add(d));
+        match (x, y) {
+            (Some(x), Some(y)) => {
+                self.x = x;
+                self.y = y;
+                Ok(())
+            }
+            _ => Err(format!("A point moves out of range in {} seconds", dt).into()),
+        }
     }
 }
 
@@ -119,3 +206,80 @@ fn points() -> Result<Vec<Point>> {
         Ok(Point { x, y, dx, dy })
     }).collect()
 }
+
+/// The original approach of stepping one second at a time until the area
+/// starts growing again
+#[cfg(test)]
+fn converge_by_stepping(grid: &Grid) -> i64 {
+    let mut grid = grid.clone();
+    let mut min_area = grid.area().unwrap();
+    let mut min_step = 0;
+    let mut step = 0;
+
+    loop {
+        let area = grid.area().unwrap();
+        if area < min_area {
+            min_area = area;
+            min_step = step;
+        }
+        if area > min_area {
+            break;
+        }
+        grid.step_by(1).unwrap();
+        step += 1;
+    }
+
+    min_step
+}
+
+#[test]
+fn convergence_matches_stepping() {
+    let grid = Grid(points().unwrap());
+    let time = grid.convergence_time().unwrap();
+
+    assert_eq!(time, converge_by_stepping(&grid));
+    assert_eq!(grid.at(time).unwrap().bounds().unwrap(), ((141, 202), (188, 197)));
+
+    // Starting part of the way there finds the same moment
+    assert_eq!(grid.at(1000).unwrap().convergence_time().unwrap(), time - 1000);
+}
+
+#[test]
+fn stepping_backwards_and_jumping() {
+    let points = vec![Point { x: 3, y: -2, dx: -1, dy: 2 }, Point { x: 0, y: 0, dx: 1, dy: 1 }];
+    let mut grid = Grid(points);
+
+    grid.step_by(2).unwrap();
+    assert_eq!(grid.bounds().unwrap(), ((1, 2), (2, 2)));
+    assert_eq!(grid.area().unwrap(), 0);
+    // A line of points still takes up a row when printed
+    assert_eq!(grid.cells().unwrap(), Some(2));
+
+    grid.step_by(-1).unwrap();
+    assert_eq!(grid.bounds().unwrap(), ((1, 2), (0, 1)));
+
+    let grid = grid.at(-1).unwrap();
+    assert_eq!(grid.bounds().unwrap(), ((0, 3), (-2, 0)));
+    assert_eq!(grid.at(-4).unwrap().bounds().unwrap(), ((-4, 7), (-10, -4)));
+    assert_eq!(grid.convergence_time().unwrap(), 2);
+
+    // Moving that far would overflow, so nothing moves
+    let mut far = grid.clone();
+    assert!(far.step_by(i64::MAX).is_err());
+    assert!(grid.at(i64::MIN).is_err());
+    assert_eq!(far.bounds().unwrap(), grid.bounds().unwrap());
+
+    let spread = Grid(vec![
+        Point { x: i64::MIN, y: 0, dx: 0, dy: 0 },
+        Point { x: 0, y: 0, dx: 0, dy: 0 },
+    ]);
+    assert_eq!(spread.cells().unwrap(), None);
+}
+
+#[test]
+fn convex_minimum_edges() {
+    assert_eq!(convex_minimum(|t| (t - 12_345).abs()), 12_345);
+    assert_eq!(convex_minimum(|t| t), 0);
+    assert_eq!(convex_minimum(|t| (t - 7).abs().max(3)), 4);
+    assert_eq!(convex_minimum(|_| 5), 0);
+}
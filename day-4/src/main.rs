@@ -1,12 +1,33 @@
 use regex::Regex;
 //use itertools::Itertools;
-use std::collections::BTreeMap;
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
 
 static INPUT: &str = include_str!("../input.txt");
 
-type Error = Box<std::error::Error>;
+type Error = Box<dyn std::error::Error>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// How many entries are sorted in memory before being spilled to disk
+const DEFAULT_RUN_LENGTH: usize = 100_000;
+
+/// How many sorted runs are merged at once, which bounds how many
+/// files are open at the same time
+const MERGE_FAN_IN: usize = 64;
+
+/// How often to check a followed log for new entries
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 struct Timestamp {
     year: u32,
@@ -23,71 +44,144 @@ impl Timestamp {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
 enum Event {
     Wake,
     Sleep,
     Start(u32),
 }
 
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Wake => write!(f, "wakes up"),
+            Event::Sleep => write!(f, "falls asleep"),
+            Event::Start(id) => write!(f, "Guard #{} begins shift", id),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 enum State {
     Asleep,
     Awake,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 struct LogEntry {
     timestamp: Timestamp,
     event: Event,
 }
 
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.timestamp, self.event)
+    }
+}
+
+/// Minute -> number of times asleep during that minute
+type Histogram = BTreeMap<u32, u32>;
+
+/// Guard ID -> that guard's histogram
+type Histograms = BTreeMap<u32, Histogram>;
+
 fn main() -> Result<()> {
-    let mut log_entries = log_entries()?;
-    log_entries.sort_by_key(|e| e.timestamp);
+    let args: Vec<_> = std::env::args().skip(1).collect();
 
-    let mut time = BTreeMap::new();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => {
+            let mut log_entries = log_entries()?;
+            log_entries.sort_by_key(|e| e.timestamp);
 
-    let mut current_guard = None;
+            let mut sleeps = Sleeps::default();
+            for entry in log_entries {
+                sleeps.record(entry)?;
+            }
+            report(&sleeps.time);
+        }
+        ["external", path] => external(path, DEFAULT_RUN_LENGTH)?,
+        ["external", path, run_length] => external(path, run_length.parse()?)?,
+        ["follow", path] => follow(path)?,
+        _ => {
+            return Err(Error::from(
+                "Usage: day-4 [external <log> [run-length] | follow <log>]",
+            ))
+        }
+    }
 
-    use self::Event::*;
-    use self::State::*;
+    Ok(())
+}
 
-    for entry in log_entries {
-        match entry.event {
-            Start(id) => {
-                current_guard = Some((id, Awake, entry.timestamp));
+/// Processes a log that may not fit in memory
+fn external(path: impl AsRef<Path>, run_length: usize) -> Result<()> {
+    let parser = LogEntryParser::new();
+    let file = BufReader::new(File::open(path)?);
+    let entries = file.lines().map(|l| parser.parse(&l?));
+
+    let mut sleeps = Sleeps::default();
+    for entry in external_sort(entries, run_length, MERGE_FAN_IN)? {
+        sleeps.record(entry?)?;
+    }
+    report(&sleeps.time);
+
+    Ok(())
+}
+
+/// Processes a log, then keeps reporting as entries are appended to it
+fn follow(path: impl AsRef<Path>) -> Result<()> {
+    let parser = LogEntryParser::new();
+    let mut file = BufReader::new(File::open(path)?);
+    let mut log = IncrementalLog::default();
+    let mut line = String::new();
+
+    loop {
+        let mut changed = false;
+
+        loop {
+            line.clear();
+            let n_read = file.read_line(&mut line)?;
+            if n_read == 0 {
+                break;
             }
-            Sleep => {
-                let (id, state, _timestamp) = current_guard.ok_or("No current guard")?;
-                assert_eq!(state, Awake, "guard {}, is already asleep", id);
-                current_guard = Some((id, Asleep, entry.timestamp));
+            if !line.ends_with('\n') {
+                // The writer hasn't finished this line yet; try again later
+                file.seek_relative(-(n_read as i64))?;
+                break;
             }
-            Wake => {
-                let (id, state, timestamp) = current_guard.ok_or("No current guard")?;
-                assert_eq!(state, Asleep, "guard {}, is already awake", id);
-
-                let minutes = time.entry(id).or_insert_with(BTreeMap::new);
-                for minute in timestamp.minutes_until(&entry.timestamp) {
-                    *minutes.entry(minute).or_insert(0) += 1;
-                }
 
-                current_guard = Some((id, Awake, entry.timestamp));
+            let line = line.trim_end();
+            match parser.parse(line).and_then(|entry| log.insert(entry)) {
+                Ok(()) => changed = true,
+                Err(e) => eprintln!("Skipping `{}`: {}", line, e),
             }
         }
+
+        if changed {
+            report(log.histograms());
+        }
+
+        thread::sleep(FOLLOW_INTERVAL);
     }
+}
 
+fn report(time: &Histograms) {
     let sleepiest_guard = time
         .iter()
         .max_by_key(|(_, x)| x.values().sum::<u32>())
         .map(|(id, _)| id);
 
     if let Some(sleepiest_guard) = sleepiest_guard {
-        let sleepiest_minute = time[&sleepiest_guard]
-            .iter()
-            .max_by_key(|(_, &count)| count)
-            .map(|(minute, _)| minute)
-            .expect("Must be some time they are asleep");
+        let sleepiest_minute = sleepiest_minute(&time[sleepiest_guard]);
 
         println!(
             "The sleepiest guard is {} at {} ({})",
@@ -103,11 +197,7 @@ fn main() -> Result<()> {
         .map(|(id, _)| id);
 
     if let Some(sleepiest_guard) = sleepiest_guard {
-        let sleepiest_minute = time[&sleepiest_guard]
-            .iter()
-            .max_by_key(|(_, &count)| count)
-            .map(|(minute, _)| minute)
-            .expect("Must be some time they are asleep");
+        let sleepiest_minute = sleepiest_minute(&time[sleepiest_guard]);
 
         println!(
             "The sleepiest guard is {} at {} ({})",
@@ -116,81 +206,542 @@ fn main() -> Result<()> {
             sleepiest_guard * sleepiest_minute
         );
     }
+}
 
-    Ok(())
+fn sleepiest_minute(minutes: &Histogram) -> u32 {
+    minutes
+        .iter()
+        .max_by_key(|(_, &count)| count)
+        .map(|(&minute, _)| minute)
+        .expect("Must be some time they are asleep")
+}
+
+/// Builds the per-guard histograms from entries in chronological order
+#[derive(Debug, Default)]
+struct Sleeps {
+    current_guard: Option<(u32, State, Timestamp)>,
+    time: Histograms,
+}
+
+impl Sleeps {
+    fn record(&mut self, entry: LogEntry) -> Result<()> {
+        use self::Event::*;
+        use self::State::*;
+
+        match entry.event {
+            Start(id) => {
+                self.current_guard = Some((id, Awake, entry.timestamp));
+            }
+            Sleep => {
+                let (id, state, _timestamp) = self.current_guard.ok_or("No current guard")?;
+                if state != Awake {
+                    return Err(format!("guard {}, is already asleep", id).into());
+                }
+                self.current_guard = Some((id, Asleep, entry.timestamp));
+            }
+            Wake => {
+                let (id, state, timestamp) = self.current_guard.ok_or("No current guard")?;
+                if state != Asleep {
+                    return Err(format!("guard {}, is already awake", id).into());
+                }
+
+                let minutes = self.time.entry(id).or_default();
+                for minute in timestamp.minutes_until(&entry.timestamp) {
+                    *minutes.entry(minute).or_insert(0) += 1;
+                }
+
+                self.current_guard = Some((id, Awake, entry.timestamp));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sorts entries using a bounded amount of memory by spilling sorted
+/// runs to temporary files and merging them back together. No more
+/// than `fan_in` runs are merged at once; when there are more, groups
+/// of runs are merged into longer runs until few enough remain.
+fn external_sort<I>(
+    entries: I,
+    run_length: usize,
+    fan_in: usize,
+) -> Result<impl Iterator<Item = Result<LogEntry>>>
+where
+    I: IntoIterator<Item = Result<LogEntry>>,
+{
+    assert!(run_length > 0, "Runs must contain at least one entry");
+    assert!(fan_in > 1, "Must merge at least two runs at once");
+
+    let mut entries = entries.into_iter().peekable();
+    let mut runs = Vec::new();
+
+    while entries.peek().is_some() {
+        let mut run = entries
+            .by_ref()
+            .take(run_length)
+            .collect::<Result<Vec<_>>>()?;
+        run.sort_by_key(|e| e.timestamp);
+        runs.push(SpilledRun::new(run.into_iter().map(Ok))?);
+    }
+
+    while runs.len() > fan_in {
+        let mut merged = Vec::with_capacity(runs.len().div_ceil(fan_in));
+        let mut runs_left = runs.into_iter().peekable();
+        while runs_left.peek().is_some() {
+            // Merging consecutive runs keeps ties in their original order
+            let group = runs_left.by_ref().take(fan_in).collect();
+            merged.push(SpilledRun::new(Merge::new(group)?)?);
+        }
+        runs = merged;
+    }
+
+    Merge::new(runs)
+}
+
+static SPILLED_RUN_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A sorted run of entries stored in a temporary file, deleted on drop
+#[derive(Debug)]
+struct SpilledRun {
+    path: PathBuf,
+}
+
+impl SpilledRun {
+    fn new(entries: impl IntoIterator<Item = Result<LogEntry>>) -> Result<Self> {
+        let count = SPILLED_RUN_COUNT.fetch_add(1, Ordering::SeqCst);
+        let path = std::env::temp_dir().join(format!("day-4-{}-{}.log", process::id(), count));
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        // Take ownership now so the file is removed even if writing fails
+        let run = SpilledRun { path };
+
+        let mut file = BufWriter::new(file);
+        for entry in entries {
+            writeln!(file, "{}", entry?)?;
+        }
+        file.flush()?;
+
+        Ok(run)
+    }
+
+    fn reader(self) -> Result<RunReader> {
+        let lines = BufReader::new(File::open(&self.path)?).lines();
+        Ok(RunReader { _run: self, lines })
+    }
+}
+
+impl Drop for SpilledRun {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[derive(Debug)]
+struct RunReader {
+    _run: SpilledRun,
+    lines: io::Lines<BufReader<File>>,
+}
+
+impl RunReader {
+    fn next(&mut self, parser: &LogEntryParser) -> Result<Option<LogEntry>> {
+        match self.lines.next() {
+            Some(line) => parser.parse(&line?).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// K-way merge of sorted runs. Ties are broken by run order, so the
+/// result matches a stable sort of the original entries.
+#[derive(Debug)]
+struct Merge {
+    parser: LogEntryParser,
+    readers: Vec<RunReader>,
+    heads: Vec<Option<LogEntry>>,
+    queue: BinaryHeap<Reverse<(Timestamp, usize)>>,
+}
+
+impl Merge {
+    fn new(runs: Vec<SpilledRun>) -> Result<Self> {
+        let parser = LogEntryParser::new();
+        let mut readers = runs
+            .into_iter()
+            .map(SpilledRun::reader)
+            .collect::<Result<Vec<_>>>()?;
+        let mut heads = Vec::with_capacity(readers.len());
+        let mut queue = BinaryHeap::with_capacity(readers.len());
+
+        for (idx, reader) in readers.iter_mut().enumerate() {
+            let head = reader.next(&parser)?;
+            if let Some(entry) = head {
+                queue.push(Reverse((entry.timestamp, idx)));
+            }
+            heads.push(head);
+        }
+
+        Ok(Merge {
+            parser,
+            readers,
+            heads,
+            queue,
+        })
+    }
+}
+
+impl Iterator for Merge {
+    type Item = Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((_, idx)) = self.queue.pop()?;
+        let entry = self.heads[idx].take().expect("Queued run has no head");
+
+        match self.readers[idx].next(&self.parser) {
+            Ok(head) => {
+                if let Some(next) = head {
+                    self.queue.push(Reverse((next.timestamp, idx)));
+                }
+                self.heads[idx] = head;
+                Some(Ok(entry))
+            }
+            Err(e) => {
+                self.queue.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+/// Maintains the per-guard histograms as entries arrive in any order.
+///
+/// Inserting an entry only replays the shift that it lands in, instead
+/// of the whole log. A shift that doesn't replay cleanly (e.g. it is
+/// still missing the entry where the guard falls asleep) contributes
+/// nothing until the rest of its entries arrive.
+#[derive(Debug, Default)]
+struct IncrementalLog {
+    entries: BTreeMap<Timestamp, Event>,
+    time: Histograms,
+}
+
+impl IncrementalLog {
+    fn histograms(&self) -> &Histograms {
+        &self.time
+    }
+
+    /// Adds an entry to the log. Only an entry with the same timestamp
+    /// as an existing one is rejected.
+    fn insert(&mut self, entry: LogEntry) -> Result<()> {
+        let LogEntry { timestamp, event } = entry;
+
+        if self.entries.contains_key(&timestamp) {
+            return Err(format!("Already have an entry at {}", timestamp).into());
+        }
+
+        let start = self
+            .entries
+            .range(..timestamp)
+            .rev()
+            .find(|(_, e)| is_start(e))
+            .map(|(&t, _)| t);
+        let end = self
+            .entries
+            .range(timestamp..)
+            .find(|(_, e)| is_start(e))
+            .map(|(&t, _)| t);
+
+        let old = self.replay(start, end);
+        self.entries.insert(timestamp, event);
+        let new = self.replay(start, end);
+
+        for (id, minutes) in old {
+            if minutes.is_empty() {
+                continue;
+            }
+            let guard = self
+                .time
+                .get_mut(&id)
+                .expect("Guard missing from histograms");
+            for (minute, count) in minutes {
+                let total = guard
+                    .get_mut(&minute)
+                    .expect("Minute missing from histogram");
+                *total -= count;
+                if *total == 0 {
+                    guard.remove(&minute);
+                }
+            }
+            if guard.is_empty() {
+                self.time.remove(&id);
+            }
+        }
+
+        add_histograms(&mut self.time, new);
+
+        Ok(())
+    }
+
+    /// The contribution of the shifts starting in `[start, end)`. Entries
+    /// before the first shift start are waiting for their guard to be
+    /// logged and don't count yet.
+    fn replay(&self, start: Option<Timestamp>, end: Option<Timestamp>) -> Histograms {
+        use std::ops::Bound::*;
+
+        let start = start.map_or(Unbounded, Included);
+        let end = end.map_or(Unbounded, Excluded);
+
+        let entries = self
+            .entries
+            .range((start, end))
+            .skip_while(|(_, e)| !is_start(e))
+            .map(|(&timestamp, &event)| LogEntry { timestamp, event });
+
+        let mut time = Histograms::new();
+        let mut shift = Vec::new();
+        for entry in entries {
+            if is_start(&entry.event) {
+                add_histograms(&mut time, replay_shift(&shift));
+                shift.clear();
+            }
+            shift.push(entry);
+        }
+        add_histograms(&mut time, replay_shift(&shift));
+
+        time
+    }
+}
+
+/// The histograms for a single shift, or nothing if it is inconsistent
+fn replay_shift(shift: &[LogEntry]) -> Histograms {
+    let mut sleeps = Sleeps::default();
+    for &entry in shift {
+        if sleeps.record(entry).is_err() {
+            return Histograms::new();
+        }
+    }
+    sleeps.time
+}
+
+fn add_histograms(total: &mut Histograms, other: Histograms) {
+    for (id, minutes) in other {
+        // A guard that never slept for a whole minute has nothing to add
+        if minutes.is_empty() {
+            continue;
+        }
+        let guard = total.entry(id).or_default();
+        for (minute, count) in minutes {
+            *guard.entry(minute).or_insert(0) += count;
+        }
+    }
+}
+
+fn is_start(event: &Event) -> bool {
+    matches!(event, Event::Start(_))
 }
 
 fn log_entries() -> Result<Vec<LogEntry>> {
-    // [1518-11-07 00:21] falls asleep
-    // wakes up
-    // Guard #1823 begins shift
-    let log_entry_regex = Regex::new(
-        r"(?x)
-        \[
-        (?P<year>\d+)
-        -
-        (?P<month>\d+)
-        -
-        (?P<day>\d+)
-        \s+
-        (?P<hour>\d+)
-        :
-        (?P<minute>\d+)
-        \]
-        \s+
-        (?:
-        (?P<sleeps>falls\s+asleep)
-        |
-        (?P<wakes>wakes\s+up)
-        |
-        (Guard\s+\#(?P<starts>\d+)\s+begins\s+shift)
+    let parser = LogEntryParser::new();
+    INPUT.lines().map(|l| parser.parse(l)).collect()
+}
+
+#[derive(Debug, Clone)]
+struct LogEntryParser {
+    regex: Regex,
+}
+
+impl LogEntryParser {
+    fn new() -> Self {
+        // [1518-11-07 00:21] falls asleep
+        // wakes up
+        // Guard #1823 begins shift
+        let regex = Regex::new(
+            r"(?x)
+            \[
+            (?P<year>\d+)
+            -
+            (?P<month>\d+)
+            -
+            (?P<day>\d+)
+            \s+
+            (?P<hour>\d+)
+            :
+            (?P<minute>\d+)
+            \]
+            \s+
+            (?:
+            (?P<sleeps>falls\s+asleep)
+            |
+            (?P<wakes>wakes\s+up)
+            |
+            (Guard\s+\#(?P<starts>\d+)\s+begins\s+shift)
+            )
+        ",
         )
-    ",
-    )
-    .unwrap();
-
-    INPUT
-        .lines()
-        .map(|l| {
-            let captures = log_entry_regex
-                .captures(l)
-                .ok_or_else(|| "No matching captures")?;
-
-            let year = captures.name("year").ok_or_else(|| "No Year")?;
-            let year = year.as_str().parse()?;
-
-            let month = captures.name("month").ok_or_else(|| "No Month")?;
-            let month = month.as_str().parse()?;
-
-            let day = captures.name("day").ok_or_else(|| "No Day")?;
-            let day = day.as_str().parse()?;
-
-            let hour = captures.name("hour").ok_or_else(|| "No Hour")?;
-            let hour = hour.as_str().parse()?;
-
-            let minute = captures.name("minute").ok_or_else(|| "No Minute")?;
-            let minute = minute.as_str().parse()?;
-
-            let timestamp = Timestamp {
-                year,
-                month,
-                day,
-                hour,
-                minute,
-            };
-
-            let event = match (
-                captures.name("sleeps"),
-                captures.name("wakes"),
-                captures.name("starts"),
-            ) {
-                (Some(_), _, _) => Event::Sleep,
-                (_, Some(_), _) => Event::Wake,
-                (_, _, Some(s)) => Event::Start(s.as_str().parse()?),
-                _ => return Err(Error::from("Unknown event type")),
-            };
-
-            Ok(LogEntry { timestamp, event })
-        })
-        .collect()
+        .unwrap();
+
+        LogEntryParser { regex }
+    }
+
+    fn parse(&self, l: &str) -> Result<LogEntry> {
+        let captures = self.regex.captures(l).ok_or("No matching captures")?;
+
+        let year = captures.name("year").ok_or("No Year")?;
+        let year = year.as_str().parse()?;
+
+        let month = captures.name("month").ok_or("No Month")?;
+        let month = month.as_str().parse()?;
+
+        let day = captures.name("day").ok_or("No Day")?;
+        let day = day.as_str().parse()?;
+
+        let hour = captures.name("hour").ok_or("No Hour")?;
+        let hour = hour.as_str().parse()?;
+
+        let minute = captures.name("minute").ok_or("No Minute")?;
+        let minute = minute.as_str().parse()?;
+
+        let timestamp = Timestamp {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+        };
+
+        let event = match (
+            captures.name("sleeps"),
+            captures.name("wakes"),
+            captures.name("starts"),
+        ) {
+            (Some(_), _, _) => Event::Sleep,
+            (_, Some(_), _) => Event::Wake,
+            (_, _, Some(s)) => Event::Start(s.as_str().parse()?),
+            _ => return Err(Error::from("Unknown event type")),
+        };
+
+        Ok(LogEntry { timestamp, event })
+    }
+}
+
+#[cfg(test)]
+fn sorted_log_entries() -> Vec<LogEntry> {
+    let mut log_entries = log_entries().unwrap();
+    log_entries.sort_by_key(|e| e.timestamp);
+    log_entries
+}
+
+#[cfg(test)]
+fn batch_histograms() -> Histograms {
+    let mut sleeps = Sleeps::default();
+    for entry in sorted_log_entries() {
+        sleeps.record(entry).unwrap();
+    }
+    sleeps.time
+}
+
+#[test]
+fn display_round_trips() {
+    let parser = LogEntryParser::new();
+    for l in INPUT.lines().take(50) {
+        let entry = parser.parse(l).unwrap();
+        assert_eq!(entry.to_string(), l);
+    }
+}
+
+#[test]
+fn external_sort_matches_in_memory_sort() {
+    let entries = log_entries().unwrap().into_iter().map(Ok);
+    let sorted = external_sort(entries, 97, MERGE_FAN_IN)
+        .unwrap()
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+
+    assert_eq!(sorted, sorted_log_entries());
+}
+
+#[test]
+fn external_sort_in_several_passes() {
+    // About 100 runs merged two or three at a time
+    for &fan_in in &[2, 3] {
+        let entries = log_entries().unwrap().into_iter().map(Ok);
+        let sorted = external_sort(entries, 11, fan_in)
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+
+        assert_eq!(sorted, sorted_log_entries());
+    }
+}
+
+#[test]
+fn external_sort_empty() {
+    let sorted = external_sort(vec![], 10, MERGE_FAN_IN).unwrap();
+    assert_eq!(sorted.count(), 0);
+}
+
+#[test]
+fn incremental_appended_in_order() {
+    let mut log = IncrementalLog::default();
+    for entry in sorted_log_entries() {
+        log.insert(entry).unwrap();
+    }
+
+    assert_eq!(log.histograms(), &batch_histograms());
+}
+
+#[test]
+fn incremental_shifts_out_of_order() {
+    let entries = sorted_log_entries();
+    let mut shifts: Vec<&[LogEntry]> = Vec::new();
+    let mut start = 0;
+    for (i, entry) in entries.iter().enumerate().skip(1) {
+        if is_start(&entry.event) {
+            shifts.push(&entries[start..i]);
+            start = i;
+        }
+    }
+    shifts.push(&entries[start..]);
+
+    let mut log = IncrementalLog::default();
+    for shift in shifts.iter().rev() {
+        for &entry in shift.iter() {
+            log.insert(entry).unwrap();
+        }
+    }
+
+    assert_eq!(log.histograms(), &batch_histograms());
+}
+
+#[test]
+fn incremental_file_order() {
+    let mut log = IncrementalLog::default();
+    for entry in log_entries().unwrap() {
+        log.insert(entry).unwrap();
+    }
+
+    assert_eq!(log.histograms(), &batch_histograms());
+}
+
+#[test]
+fn incremental_inconsistent_shifts_contribute_nothing() {
+    let entries = sorted_log_entries();
+    assert_eq!(entries[1].event, Event::Sleep);
+    assert_eq!(entries[2].event, Event::Wake);
+
+    let mut log = IncrementalLog::default();
+    log.insert(entries[0]).unwrap();
+    log.insert(entries[2]).unwrap();
+    assert!(log.histograms().is_empty());
+
+    log.insert(entries[1]).unwrap();
+    assert!(!log.histograms().is_empty());
+    assert!(log.insert(entries[1]).is_err());
+
+    for &entry in &entries[3..] {
+        log.insert(entry).unwrap();
+    }
+    assert_eq!(log.histograms(), &batch_histograms());
 }
@@ -1,53 +1,667 @@
 use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, IsTerminal, Write};
+use std::thread;
+use std::time::Instant;
 
 static INPUT: &str = include_str!("../input.txt");
 
-fn main() {
-    let polymer: Vec<_> = INPUT.trim().chars().collect();
+type Error = Box<dyn std::error::Error>;
+type Result<T, E = Error> = std::result::Result<T, E>;
 
-    let units: BTreeSet<_> = polymer.iter().map(char::to_ascii_uppercase).collect();
+fn main() -> Result<()> {
+    let options = match Command::parse(std::env::args().skip(1))? {
+        Command::Run(options) => options,
+        Command::Bench => {
+            bench();
+            return Ok(());
+        }
+    };
+
+    let rules = match &options.rules {
+        Some(path) => Rules::parse(&fs::read_to_string(path)?)?,
+        None => Rules::default(),
+    };
+
+    let polymer = options
+        .polymer
+        .as_deref()
+        .unwrap_or(INPUT)
+        .trim()
+        .as_bytes();
+
+    let mut trace_file = options
+        .trace
+        .as_deref()
+        .map(TraceWriter::create)
+        .transpose()?;
+    let mut trace = Vec::new();
+    let reacted = if trace_file.is_some() || options.replay {
+        complete_react_with(polymer, &rules, |step| {
+            if let Some(trace_file) = &mut trace_file {
+                trace_file.write(step);
+            }
+            // Only replaying needs every step at once
+            if options.replay {
+                trace.push(step);
+            }
+        })
+    } else {
+        complete_react(polymer, &rules)
+    };
+
+    if let Some(trace_file) = trace_file {
+        trace_file.finish()?;
+    }
+    if options.replay {
+        replay(polymer, &trace)?;
+    }
+
+    println!("Reacted polymer is {} units", reacted.len());
+
+    let all_reactions = all_reactions(polymer, &reacted, &rules);
+
+    println!("Unit | Length");
+    for (unit, reaction) in &all_reactions {
+        println!("{:>4} | {}", *unit as char, reaction.len());
+    }
+
+    let shortest = all_reactions.iter().min_by_key(|(_, r)| r.len());
+
+    match shortest {
+        Some((unit, reaction)) => println!(
+            "Resulting polymer is {} units (removing {})",
+            reaction.len(),
+            *unit as char
+        ),
+        None => println!("There are no units to remove"),
+    }
+
+    Ok(())
+}
+
+const USAGE: &str =
+    "Usage: day-5 [--rules <file>] [--polymer <units>] [--trace <file>] [--replay] | bench";
+
+#[derive(Debug)]
+enum Command {
+    Run(Options),
+    Bench,
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    rules: Option<String>,
+    polymer: Option<String>,
+    trace: Option<String>,
+    replay: bool,
+}
+
+impl Command {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self> {
+        let mut args = args.into_iter().peekable();
+        let mut options = Options::default();
+
+        if args.peek().map(String::as_str) == Some("bench") {
+            args.next();
+            return match args.next() {
+                None => Ok(Command::Bench),
+                Some(_) => Err(Error::from(USAGE)),
+            };
+        }
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| Error::from(USAGE));
+            match arg.as_str() {
+                "--rules" => options.rules = Some(value()?),
+                "--polymer" => options.polymer = Some(value()?),
+                "--trace" => options.trace = Some(value()?),
+                "--replay" => options.replay = true,
+                _ => return Err(Error::from(USAGE)),
+            }
+        }
+
+        Ok(Command::Run(options))
+    }
+}
+
+/// Reacts the polymer once for each unit removed from it.
+///
+/// Opposing polarity rules pair up the two cases of a letter, so a
+/// unit is both cases and is keyed by the uppercase one. Other rules
+/// don't say which bytes belong together, so each distinct byte is a
+/// unit of its own.
+fn all_reactions(polymer: &[u8], reacted: &[u8], rules: &Rules) -> BTreeMap<u8, Vec<u8>> {
+    let paired = rules.only_opposing_polarity();
+    let units: Vec<_> = polymer
+        .iter()
+        .map(|&c| unit_of(c, paired))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+
+    // With opposing polarity reactions, removing a unit and reacting
+    // gives the same result whether we start from the original or the
+    // already-reacted polymer, and the reacted one is much shorter.
+    // Other rules may have consumed the removed unit to make new ones.
+    let start = if paired {
+        reacted
+    } else {
+        polymer
+    };
+
+    let n_threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = units.len().div_ceil(n_threads).max(1);
 
-    let all_reactions: BTreeMap<_, _> = units.iter().map(|&c| (c, complete_react(remove_unit(&polymer, c)))).collect();
+    thread::scope(|s| {
+        let workers: Vec<_> = units
+            .chunks(chunk_size)
+            .map(|units| {
+                s.spawn(move || {
+                    units
+                        .iter()
+                        .map(|&c| (c, complete_react(&remove_unit(start, c, paired), rules)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
 
-    let min_length = all_reactions.values().map(|r| r.len()).min();
+        workers
+            .into_iter()
+            .flat_map(|w| w.join().expect("Reaction thread panicked"))
+            .collect()
+    })
+}
 
-    println!("Resulting polymer is {:?} units", min_length);
+fn unit_of(c: u8, paired: bool) -> u8 {
+    if paired {
+        c.to_ascii_uppercase()
+    } else {
+        c
+    }
 }
 
-fn remove_unit(polymer: &[char], unit: char) -> Vec<char> {
+fn remove_unit(polymer: &[u8], unit: u8, paired: bool) -> Vec<u8> {
     let mut polymer = polymer.to_owned();
-    polymer.retain(|c| !c.eq_ignore_ascii_case(&unit));
+    polymer.retain(|&c| unit_of(c, paired) != unit);
     polymer
 }
 
-fn complete_react(mut polymer: Vec<char>) -> Vec<char> {
-    loop {
-        let start_len = polymer.len();
-        polymer = react(polymer);
-        if polymer.len() == start_len {
-            return polymer;
+/// Reacts adjacent units until no rule applies, always reacting the
+/// leftmost possible pair first.
+fn complete_react(polymer: &[u8], rules: &Rules) -> Vec<u8> {
+    complete_react_with(polymer, rules, |_| {})
+}
+
+/// Like `complete_react`, but reports every reaction as it happens.
+fn complete_react_with(
+    polymer: &[u8],
+    rules: &Rules,
+    mut on_reaction: impl FnMut(Step),
+) -> Vec<u8> {
+    let mut reacted = Vec::with_capacity(polymer.len());
+
+    for (i, &unit) in polymer.iter().enumerate() {
+        // The current polymer is everything reacted so far followed by
+        // the units we haven't looked at yet.
+        let unvisited = polymer.len() - i - 1;
+        let mut next = Some(unit);
+
+        while let Some(unit) = next.take() {
+            let previous = match reacted.last() {
+                Some(&previous) => previous,
+                None => {
+                    reacted.push(unit);
+                    continue;
+                }
+            };
+
+            let reaction = match rules.reaction(previous, unit) {
+                Some(reaction) => reaction,
+                None => {
+                    reacted.push(unit);
+                    continue;
+                }
+            };
+
+            reacted.pop();
+            if let Reaction::Transform(product) = reaction {
+                next = Some(product);
+            }
+
+            on_reaction(Step {
+                position: reacted.len(),
+                pair: (previous, unit),
+                reaction,
+                length: reacted.len() + next.iter().count() + unvisited,
+            });
+        }
+    }
+
+    reacted
+}
+
+/// A single reaction during `complete_react`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Step {
+    /// Index of the first unit of the pair in the polymer at that time
+    position: usize,
+    pair: (u8, u8),
+    reaction: Reaction,
+    /// Length of the polymer after reacting
+    length: usize,
+}
+
+impl Step {
+    fn apply(&self, polymer: &mut Vec<u8>) {
+        let pair = self.position..self.position + 2;
+        match self.reaction {
+            Reaction::Annihilate => polymer.splice(pair, None),
+            Reaction::Transform(product) => polymer.splice(pair, Some(product)),
+        };
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let product = match self.reaction {
+            Reaction::Annihilate => '-',
+            Reaction::Transform(product) => product as char,
+        };
+        write!(
+            f,
+            "{} {}{} {} {}",
+            self.position, self.pair.0 as char, self.pair.1 as char, product, self.length
+        )
+    }
+}
+
+/// Writes one step per line as `position pair product length`, with `-`
+/// as the product of an annihilation, as the steps happen.
+#[derive(Debug)]
+struct TraceWriter {
+    file: BufWriter<File>,
+    /// The first failed write; later steps are not written
+    result: io::Result<()>,
+}
+
+impl TraceWriter {
+    fn create(path: &str) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "# position pair product length")?;
+        Ok(TraceWriter { file, result: Ok(()) })
+    }
+
+    fn write(&mut self, step: Step) {
+        if self.result.is_ok() {
+            self.result = writeln!(self.file, "{}", step);
+        }
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.result?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Shows the polymer before each reaction with the reacting pair
+/// highlighted, waiting for Enter between steps when run in a terminal.
+fn replay(polymer: &[u8], trace: &[Step]) -> Result<()> {
+    let stdin = io::stdin();
+    let interactive = stdin.is_terminal();
+    let mut line = String::new();
+    let width = polymer.len();
+    let mut polymer = polymer.to_owned();
+
+    for step in trace {
+        let (before, rest) = polymer.split_at(step.position);
+        let (pair, after) = rest.split_at(2);
+        let padding = width - polymer.len();
+
+        print!(
+            "{}\x1b[7m{}\x1b[0m{}{:padding$}  {}",
+            String::from_utf8_lossy(before),
+            String::from_utf8_lossy(pair),
+            String::from_utf8_lossy(after),
+            "",
+            describe(step),
+            padding = padding,
+        );
+        io::stdout().flush()?;
+
+        // The user's Enter ends the line when stepping interactively
+        line.clear();
+        if !interactive || stdin.lock().read_line(&mut line)? == 0 {
+            println!();
+        }
+
+        step.apply(&mut polymer);
+    }
+
+    println!(
+        "{}{:padding$}  No further actions can be taken.",
+        String::from_utf8_lossy(&polymer),
+        "",
+        padding = width - polymer.len(),
+    );
+
+    Ok(())
+}
+
+fn describe(step: &Step) -> String {
+    let (a, b) = (step.pair.0 as char, step.pair.1 as char);
+    match step.reaction {
+        Reaction::Annihilate => format!("'{}{}' is removed.", a, b),
+        Reaction::Transform(product) => {
+            format!("'{}{}' becomes '{}'.", a, b, product as char)
         }
     }
 }
 
-fn react(polymer: Vec<char>) -> Vec<char> {
-    let mut i = 0;
-    let mut next = Vec::new();
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Reaction {
+    /// Both units are destroyed
+    Annihilate,
+    /// Both units are replaced by the given unit
+    Transform(u8),
+}
 
-    loop {
-        match (polymer.get(i), polymer.get(i+1)) {
-            (Some(&a), Some(&b)) if opposing_polarity(a, b) => i += 2,
-            (Some(&a), _) => {
-                next.push(a);
-                i += 1;
+/// What happens when one unit is immediately followed by another
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rules {
+    reactions: Vec<Option<Reaction>>,
+}
+
+impl Default for Rules {
+    /// The same type of unit with opposing polarities annihilate.
+    fn default() -> Self {
+        let mut rules = Rules::empty();
+        for lower in b'a'..=b'z' {
+            let upper = lower.to_ascii_uppercase();
+            rules.insert(lower, upper, Reaction::Annihilate);
+            rules.insert(upper, lower, Reaction::Annihilate);
+        }
+        rules
+    }
+}
+
+impl Rules {
+    fn empty() -> Self {
+        Rules {
+            reactions: vec![None; 256 * 256],
+        }
+    }
+
+    /// Parses one rule per line. `aA ->` means the pair annihilates and
+    /// `ab -> c` means the pair becomes `c`. Blank lines and lines
+    /// starting with `#` are ignored.
+    fn parse(s: &str) -> Result<Self> {
+        let mut rules = Rules::empty();
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (pair, product) = line
+                .split_once("->")
+                .ok_or_else(|| format!("Line {}: missing `->`", i + 1))?;
+
+            let reaction = match product.trim().as_bytes() {
+                [] => Reaction::Annihilate,
+                &[product] => Reaction::Transform(product),
+                _ => return Err(format!("Line {}: product must be one unit", i + 1).into()),
+            };
+
+            match *pair.trim().as_bytes() {
+                [a, b] => rules.insert(a, b, reaction),
+                _ => return Err(format!("Line {}: reactions need two units", i + 1).into()),
             }
-            _ => break,
         }
+
+        Ok(rules)
+    }
+
+    fn insert(&mut self, a: u8, b: u8, reaction: Reaction) {
+        self.reactions[Self::index(a, b)] = Some(reaction);
+    }
+
+    fn reaction(&self, a: u8, b: u8) -> Option<Reaction> {
+        self.reactions[Self::index(a, b)]
+    }
+
+    fn index(a: u8, b: u8) -> usize {
+        usize::from(a) * 256 + usize::from(b)
     }
 
-    next
+    fn only_opposing_polarity(&self) -> bool {
+        self.reactions.iter().enumerate().all(|(i, r)| match r {
+            None => true,
+            Some(Reaction::Annihilate) => {
+                let (a, b) = ((i / 256) as u8, (i % 256) as u8);
+                opposing_polarity(a, b)
+            }
+            Some(Reaction::Transform(_)) => false,
+        })
+    }
 }
 
-fn opposing_polarity(a: char, b: char) -> bool {
+fn opposing_polarity(a: u8, b: u8) -> bool {
     a.eq_ignore_ascii_case(&b) && a != b
 }
+
+/// The original implementation, which removes non-overlapping pairs
+/// until nothing changes. Kept to compare against.
+mod pairwise {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    pub fn all_reactions(polymer: &[char]) -> BTreeMap<char, Vec<char>> {
+        let units: BTreeSet<_> = polymer.iter().map(char::to_ascii_uppercase).collect();
+
+        units
+            .iter()
+            .map(|&c| (c, complete_react(remove_unit(polymer, c))))
+            .collect()
+    }
+
+    fn remove_unit(polymer: &[char], unit: char) -> Vec<char> {
+        let mut polymer = polymer.to_owned();
+        polymer.retain(|c| !c.eq_ignore_ascii_case(&unit));
+        polymer
+    }
+
+    pub fn complete_react(mut polymer: Vec<char>) -> Vec<char> {
+        loop {
+            let start_len = polymer.len();
+            polymer = react(polymer);
+            if polymer.len() == start_len {
+                return polymer;
+            }
+        }
+    }
+
+    fn react(polymer: Vec<char>) -> Vec<char> {
+        let mut i = 0;
+        let mut next = Vec::new();
+
+        loop {
+            match (polymer.get(i), polymer.get(i + 1)) {
+                (Some(&a), Some(&b)) if opposing_polarity(a, b) => i += 2,
+                (Some(&a), _) => {
+                    next.push(a);
+                    i += 1;
+                }
+                _ => break,
+            }
+        }
+
+        next
+    }
+
+    fn opposing_polarity(a: char, b: char) -> bool {
+        a.eq_ignore_ascii_case(&b) && a != b
+    }
+}
+
+/// Compares the stack-based reducer to the original implementation on
+/// the puzzle input. Run with `cargo run --release -- bench`.
+fn bench() {
+    let polymer = INPUT.trim().as_bytes();
+    let chars: Vec<_> = INPUT.trim().chars().collect();
+    let rules = Rules::default();
+
+    let pairwise_1 = time("pairwise part 1", || {
+        pairwise::complete_react(chars.clone()).len()
+    });
+    let stack_1 = time("stack part 1", || complete_react(polymer, &rules).len());
+    println!(
+        "Speedup: {:.1}x",
+        pairwise_1.as_secs_f64() / stack_1.as_secs_f64()
+    );
+
+    let pairwise_2 = time("pairwise part 2", || {
+        pairwise::all_reactions(&chars).values().map(Vec::len).min()
+    });
+    let stack_2 = time("stack part 2", || {
+        let reacted = complete_react(polymer, &rules);
+        all_reactions(polymer, &reacted, &rules)
+            .values()
+            .map(Vec::len)
+            .min()
+    });
+    println!(
+        "Speedup: {:.1}x",
+        pairwise_2.as_secs_f64() / stack_2.as_secs_f64()
+    );
+}
+
+fn time<T: std::fmt::Debug>(name: &str, f: impl FnOnce() -> T) -> std::time::Duration {
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    println!("{:<16} {:>12?} ({:?})", name, elapsed, result);
+    elapsed
+}
+
+#[cfg(test)]
+const EXAMPLE: &[u8] = b"dabAcCaCBAcCcaDA";
+
+#[test]
+fn complete_react_example() {
+    assert_eq!(complete_react(EXAMPLE, &Rules::default()), b"dabCBAcaDA");
+}
+
+#[test]
+fn all_reactions_example() {
+    let rules = Rules::default();
+    let reacted = complete_react(EXAMPLE, &rules);
+    let all_reactions = all_reactions(EXAMPLE, &reacted, &rules);
+
+    assert_eq!(all_reactions[&b'A'], b"dbCBcD");
+    assert_eq!(all_reactions[&b'B'], b"daCAcaDA");
+    assert_eq!(all_reactions[&b'C'], b"daDA");
+    assert_eq!(all_reactions[&b'D'], b"abCBAc");
+}
+
+#[test]
+fn matches_pairwise() {
+    let polymer = INPUT.trim().as_bytes();
+    let chars: Vec<_> = INPUT.trim().chars().collect();
+
+    let rules = Rules::default();
+    let reacted = complete_react(polymer, &rules);
+    let expected: String = pairwise::complete_react(chars.clone())
+        .into_iter()
+        .collect();
+    assert_eq!(reacted, expected.as_bytes());
+
+    let lengths: BTreeMap<_, _> = all_reactions(polymer, &reacted, &rules)
+        .into_iter()
+        .map(|(c, r)| (c as char, r.len()))
+        .collect();
+    let expected: BTreeMap<_, _> = pairwise::all_reactions(&chars)
+        .into_iter()
+        .map(|(c, r)| (c, r.len()))
+        .collect();
+    assert_eq!(lengths, expected);
+}
+
+#[test]
+fn parsed_polarity_rules_match_default() {
+    let table: String = (b'a'..=b'z')
+        .map(|c| {
+            let (l, u) = (c as char, c.to_ascii_uppercase() as char);
+            format!("{}{} ->\n{}{} ->\n", l, u, u, l)
+        })
+        .collect();
+    let rules = Rules::parse(&table).unwrap();
+
+    assert_eq!(rules, Rules::default());
+    assert!(rules.only_opposing_polarity());
+}
+
+#[test]
+fn transform_rules() {
+    let rules = Rules::parse("# make c\nab -> c\ncc ->\n").unwrap();
+
+    assert!(!rules.only_opposing_polarity());
+    assert_eq!(complete_react(b"abab", &rules), b"");
+    assert_eq!(complete_react(b"aabb", &rules), b"acb");
+    assert_eq!(complete_react(b"ababa", &rules), b"a");
+
+    let reacted = complete_react(b"abcab", &rules);
+    assert_eq!(reacted, b"c");
+    let all_reactions = all_reactions(b"abcab", &reacted, &rules);
+    assert_eq!(all_reactions[&b'c'], b"");
+
+    // Without polarity, the two cases of a letter are different units
+    let reacted = complete_react(b"abAB", &rules);
+    let by_byte = self::all_reactions(b"abAB", &reacted, &rules);
+    assert_eq!(by_byte.keys().cloned().collect::<Vec<_>>(), b"ABab");
+    assert_eq!(by_byte[&b'A'], b"cB");
+    assert_eq!(by_byte[&b'a'], b"bAB");
+}
+
+#[test]
+fn parse_errors() {
+    assert!(Rules::parse("ab c").is_err());
+    assert!(Rules::parse("abc -> d").is_err());
+    assert!(Rules::parse("ab -> cd").is_err());
+}
+
+#[test]
+fn trace_example() {
+    let mut trace = Vec::new();
+    let reacted = complete_react_with(EXAMPLE, &Rules::default(), |s| trace.push(s));
+
+    let steps: Vec<_> = trace.iter().map(Step::to_string).collect();
+    assert_eq!(steps, ["4 cC - 14", "3 Aa - 12", "6 cC - 10"]);
+
+    let mut polymer = EXAMPLE.to_owned();
+    for step in &trace {
+        step.apply(&mut polymer);
+        assert_eq!(polymer.len(), step.length);
+    }
+    assert_eq!(polymer, reacted);
+}
+
+#[test]
+fn trace_transform() {
+    let rules = Rules::parse("ab -> c\ncc ->\n").unwrap();
+
+    let mut trace = Vec::new();
+    let reacted = complete_react_with(b"xabab", &rules, |s| trace.push(s));
+
+    let steps: Vec<_> = trace.iter().map(Step::to_string).collect();
+    assert_eq!(steps, ["1 ab c 4", "2 ab c 3", "1 cc - 1"]);
+
+    let mut polymer = b"xabab".to_vec();
+    for step in &trace {
+        step.apply(&mut polymer);
+        assert_eq!(polymer.len(), step.length);
+    }
+    assert_eq!(polymer, reacted);
+}
@@ -1,9 +1,12 @@
 use itertools::Itertools;
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 
 static INPUT: &str = include_str!("../input.txt");
 
-type Error = Box<std::error::Error>;
+type Error = Box<dyn std::error::Error>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -21,71 +24,672 @@ impl Nearest {
     }
 }
 
+/// Cells in the safe region are closer than this to all coordinates combined
+const MAX_TOTAL_DISTANCE: f64 = 10_000.0;
+
 fn main() -> Result<()> {
+    let mut max_total_distance = MAX_TOTAL_DISTANCE;
+    let mut render = false;
+    let mut image = None;
+    let mut metric = "manhattan".to_string();
+    let mut weights = (1, 1);
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--render" => render = true,
+            "--image" => image = Some(args.next().ok_or("Missing image path")?),
+            "--metric" => metric = args.next().ok_or("Missing metric")?,
+            "--weights" => {
+                let w = args.next().ok_or("Missing weights")?;
+                let (x, y) = w.split_once(',').ok_or("Weights must be `x,y`")?;
+                weights = (x.trim().parse()?, y.trim().parse()?);
+            }
+            _ => match arg.parse() {
+                Ok(distance) => max_total_distance = check_threshold(distance)?,
+                Err(_) => return Err(format!("Unknown argument {}", arg).into()),
+            },
+        }
+    }
+
+    let (wx, wy) = weights;
+    let metric: Box<dyn Metric> = match metric.as_str() {
+        "manhattan" => Box::new(Manhattan::weighted(wx, wy)?),
+        "chebyshev" => Box::new(Chebyshev::weighted(wx, wy)?),
+        "euclidean" => Box::new(Euclidean::weighted(wx, wy)?),
+        _ => return Err(format!("Unknown metric {}", metric).into()),
+    };
+
     let coords = coords()?;
 
     let bounds = Bounds::new(&coords)?;
     println!("Bounds of area: {:?}", bounds);
 
-    let find_nearest = |coord| {
-        // Find distance to all coordinates from current point
-        let mut distances: Vec<_> = coords.iter().map(|&c| (c, distance(coord, c))).collect();
+    let grid = metric.voronoi(&coords, &bounds);
 
-        // Find closest coordinate(s)
-        distances.sort_by_key(|&(_, d)| d);
-        let (nearest, distance) = distances[0];
-        let n_nearest = distances.iter().filter(|&&(_, d)| d == distance).count();
+    let infinite_coords = metric.infinite_coords(&coords, &bounds);
 
-        if n_nearest == 1 {
-            Nearest::One(nearest)
-        } else {
-            Nearest::Multiple
-        }
-    };
+    if let Some((coord, count)) = largest_finite_area(&grid, &infinite_coords) {
+        println!("Coordinate {:?} has an area of {}", coord, count);
+    }
+
+    let within = metric.safe_region_size(&coords, max_total_distance);
+
+    println!("There are {} coordinates with a total of {} distance", within, max_total_distance);
 
-    let mut grid = BTreeMap::new();
-    for coord in bounds.grid_coords() {
-        grid.insert(coord, find_nearest(coord));
+    if render || image.is_some() {
+        let map = Map::new(&*metric, &coords, &bounds, &grid, &infinite_coords, max_total_distance);
+
+        if render {
+            print!("{}", map.to_ansi());
+        }
+        if let Some(path) = image {
+            map.write_ppm(&mut BufWriter::new(File::create(path)?))?;
+        }
     }
 
-    // Any coordinate that leaks into the fringe is infinite
-    let infinite_coords: BTreeSet<_> = bounds
-        .fringe_coords()
-        .filter_map(|coord| find_nearest(coord).into_option())
-        .collect();
-    let non_infinite_coords = &coords - &infinite_coords;
+    Ok(())
+}
 
+fn largest_finite_area(
+    grid: &BTreeMap<Coord, Nearest>,
+    infinite_coords: &BTreeSet<Coord>,
+) -> Option<(Coord, usize)> {
     let non_infinite_grid_coords = grid
         .values()
         .filter_map(|c| c.into_option())
-        .filter(|c| non_infinite_coords.contains(c));
+        .filter(|c| !infinite_coords.contains(c));
 
     let mut counts = BTreeMap::new();
     for coord in non_infinite_grid_coords {
         *counts.entry(coord).or_insert(0) += 1;
     }
 
-    if let Some((coord, count)) = counts.into_iter().max_by_key(|&(_, count)| count) {
-        println!("Coordinate {:?} has an area of {}", coord, count);
+    counts.into_iter().max_by_key(|&(_, count)| count)
+}
+
+type Coord = (i32, i32);
+
+/// A way of measuring how far apart two cells are.
+///
+/// Every metric here is a norm with an integer weight on each axis, so
+/// the length never shrinks when either component grows. Distances are
+/// `f64` to allow for Euclidean distance, but equal distances between
+/// integer coordinates always compare equal.
+trait Metric {
+    /// The length of the vector `(dx, dy)`
+    fn length(&self, dx: i32, dy: i32) -> f64;
+
+    fn distance(&self, a: Coord, b: Coord) -> f64 {
+        self.length(b.0 - a.0, b.1 - a.1)
     }
 
-    let within_10000 = bounds.wide_coords().filter(|&coord| {
-        coords.iter().map(|&c| distance(c, coord)).sum::<i32>() < 10_000
-    }).count();
+    /// The coordinates that are nearest to infinitely many cells
+    fn infinite_coords(&self, coords: &BTreeSet<Coord>, bounds: &Bounds) -> BTreeSet<Coord>;
 
-    println!("There are {} coordinates with a total of 10,000 distance", within_10000);
+    /// The nearest coordinate for every cell in the bounds
+    fn voronoi(&self, coords: &BTreeSet<Coord>, bounds: &Bounds) -> BTreeMap<Coord, Nearest> {
+        bounds
+            .inclusive_coords()
+            .map(|coord| (coord, find_nearest(self, coords, coord)))
+            .collect()
+    }
 
-    Ok(())
+    /// Counts the cells whose total distance to all coordinates is less
+    /// than `limit`.
+    fn safe_region_size(&self, coords: &BTreeSet<Coord>, limit: f64) -> usize {
+        let bounds = match Bounds::new(coords) {
+            Ok(bounds) => bounds,
+            Err(_) => return 0,
+        };
+
+        // Every step away from the bounds along an axis moves away from
+        // every coordinate
+        let n = coords.len() as f64;
+        let reach_x = (limit / (n * self.length(1, 0))).ceil() as i32;
+        let reach_y = (limit / (n * self.length(0, 1))).ceil() as i32;
+
+        bounds
+            .expand(reach_x, reach_y)
+            .inclusive_coords()
+            .filter(|&coord| {
+                coords.iter().map(|&c| self.distance(c, coord)).sum::<f64>() < limit
+            })
+            .count()
+    }
 }
 
-type Coord = (i32, i32);
+fn find_nearest<M>(metric: &M, coords: &BTreeSet<Coord>, coord: Coord) -> Nearest
+where
+    M: Metric + ?Sized,
+{
+    // Find distance to all coordinates from current point
+    let mut distances: Vec<_> = coords.iter().map(|&c| (c, metric.distance(coord, c))).collect();
+
+    // Find closest coordinate(s)
+    distances.sort_by(|&(_, a), &(_, b)| a.partial_cmp(&b).expect("Distance was NaN"));
+    let (nearest, distance) = distances[0];
+    let n_nearest = distances.iter().filter(|&&(_, d)| d == distance).count();
+
+    if n_nearest == 1 {
+        Nearest::One(nearest)
+    } else {
+        Nearest::Multiple
+    }
+}
+
+/// Distances are measured in whole cells, so the threshold has to fit
+/// in the same integers that the cells use.
+fn check_threshold(distance: f64) -> Result<f64> {
+    if (0.0..=f64::from(i32::MAX)).contains(&distance) {
+        Ok(distance)
+    } else {
+        Err(format!("Threshold must be between 0 and {}", i32::MAX).into())
+    }
+}
+
+fn check_weights(wx: i32, wy: i32) -> Result<()> {
+    if wx > 0 && wy > 0 {
+        Ok(())
+    } else {
+        Err(Error::from("Weights must be positive"))
+    }
+}
+
+/// `wx * |dx| + wy * |dy|`
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Manhattan {
+    wx: i32,
+    wy: i32,
+}
+
+impl Default for Manhattan {
+    fn default() -> Self {
+        Manhattan { wx: 1, wy: 1 }
+    }
+}
+
+impl Manhattan {
+    fn weighted(wx: i32, wy: i32) -> Result<Self> {
+        check_weights(wx, wy)?;
+        Ok(Manhattan { wx, wy })
+    }
+}
+
+impl Metric for Manhattan {
+    fn length(&self, dx: i32, dy: i32) -> f64 {
+        f64::from(self.wx * dx.abs() + self.wy * dy.abs())
+    }
+
+    /// Far enough to the right of row `y`, the distance to `(cx, cy)` is
+    /// `wx * (x - cx) + wy * |y - cy|`, so whichever coordinate maximizes
+    /// `wx * cx - wy * |y - cy|` owns the rest of the row, unless it is
+    /// tied. Outside of the bounds the order doesn't change any more, so
+    /// checking each row and column within the bounds in all four
+    /// directions finds every coordinate with an infinite area.
+    ///
+    /// This plays the role that convex hull membership does for
+    /// Euclidean distance. With Manhattan distance, a coordinate can be
+    /// on the hull and still be finite, so the hull alone isn't enough.
+    fn infinite_coords(&self, coords: &BTreeSet<Coord>, bounds: &Bounds) -> BTreeSet<Coord> {
+        let Manhattan { wx, wy } = *self;
+        let mut infinite = BTreeSet::new();
+
+        for y in bounds.min_y..=bounds.max_y {
+            infinite.extend(unique_max_by_key(coords, |&(cx, cy)| wx * cx - wy * (y - cy).abs()));
+            infinite.extend(unique_max_by_key(coords, |&(cx, cy)| -wx * cx - wy * (y - cy).abs()));
+        }
+
+        for x in bounds.min_x..=bounds.max_x {
+            infinite.extend(unique_max_by_key(coords, |&(cx, cy)| wy * cy - wx * (x - cx).abs()));
+            infinite.extend(unique_max_by_key(coords, |&(cx, cy)| -wy * cy - wx * (x - cx).abs()));
+        }
+
+        infinite
+    }
+
+    fn voronoi(&self, coords: &BTreeSet<Coord>, bounds: &Bounds) -> BTreeMap<Coord, Nearest> {
+        if *self == Manhattan::default() {
+            voronoi(coords, bounds)
+        } else {
+            bounds
+                .inclusive_coords()
+                .map(|coord| (coord, find_nearest(self, coords, coord)))
+                .collect()
+        }
+    }
+
+    /// The total distance to `(x, y)` is the sum of the total distances
+    /// along each axis, so those can be computed separately and paired
+    /// up.
+    fn safe_region_size(&self, coords: &BTreeSet<Coord>, limit: f64) -> usize {
+        // Totals are integers, so `total < limit` exactly when
+        // `total < ceil(limit)`
+        let limit = limit.ceil() as i64;
+
+        let xs = coords.iter().map(|&(x, _)| x).sorted();
+        let ys = coords.iter().map(|&(_, y)| y).sorted();
+
+        let x_distances = axis_distances(&xs, self.wx, limit);
+        let mut y_distances = axis_distances(&ys, self.wy, limit);
+        y_distances.sort();
+
+        x_distances
+            .iter()
+            .map(|&dx| y_distances.partition_point(|&dy| dx + dy < limit))
+            .sum()
+    }
+}
+
+/// `max(wx * |dx|, wy * |dy|)`
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Chebyshev {
+    wx: i32,
+    wy: i32,
+}
+
+impl Default for Chebyshev {
+    fn default() -> Self {
+        Chebyshev { wx: 1, wy: 1 }
+    }
+}
+
+impl Chebyshev {
+    fn weighted(wx: i32, wy: i32) -> Result<Self> {
+        check_weights(wx, wy)?;
+        Ok(Chebyshev { wx, wy })
+    }
+}
+
+impl Metric for Chebyshev {
+    fn length(&self, dx: i32, dy: i32) -> f64 {
+        f64::from(i32::max(self.wx * dx.abs(), self.wy * dy.abs()))
+    }
+
+    /// Far out in a quadrant, the distance to `(cx, cy)` is
+    /// `max(X - X_c, Y - Y_c)`, using `X = ±wx * x` and `Y = ±wy * y`
+    /// with signs that make both terms positive. Along a diagonal line
+    /// where `u = X - Y` is constant, that is `Y + max(u - X_c, -Y_c)`,
+    /// so the coordinate minimizing `max(u - X_c, -Y_c)` owns the rest of
+    /// the line, unless it is tied.
+    ///
+    /// This is the Manhattan row and column check rotated by 45°. Once
+    /// `u` is beyond every `X_c - Y_c` the order doesn't change any more,
+    /// and lines that extend straight out from the bounds have the same
+    /// owner as the diagonals at the ends of that range.
+    fn infinite_coords(&self, coords: &BTreeSet<Coord>, _bounds: &Bounds) -> BTreeSet<Coord> {
+        let Chebyshev { wx, wy } = *self;
+        // Lines with no cells on them can't be owned
+        let step = gcd(wx, wy);
+        let mut infinite = BTreeSet::new();
+
+        for &(sx, sy) in &[(1, 1), (1, -1), (-1, 1), (-1, -1)] {
+            let key = |&(cx, cy): &Coord| (sx * wx * cx, sy * wy * cy);
 
-fn distance(a: Coord, b: Coord) -> i32 {
-    let [x0, x1] = { let mut t = [a.0, b.0]; t.sort(); t };
-    let [y0, y1] = { let mut t = [a.1, b.1]; t.sort(); t };
-    x1 - x0 + y1 - y0
+            let diagonals = coords.iter().map(key).map(|(x, y)| x - y);
+            let (lo, hi) = match diagonals.minmax().into_option() {
+                Some(range) => range,
+                None => continue,
+            };
+            let lo = lo.div_euclid(step) * step - step;
+            let hi = hi.div_euclid(step) * step + 2 * step;
+
+            for u in (lo..=hi).step_by(step as usize) {
+                infinite.extend(unique_max_by_key(coords, |c| {
+                    let (x, y) = key(c);
+                    -i32::max(u - x, -y)
+                }));
+            }
+        }
+
+        infinite
+    }
 }
 
+fn gcd(a: i32, b: i32) -> i32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// `sqrt((wx * dx)² + (wy * dy)²)`
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Euclidean {
+    wx: i32,
+    wy: i32,
+}
+
+impl Default for Euclidean {
+    fn default() -> Self {
+        Euclidean { wx: 1, wy: 1 }
+    }
+}
+
+impl Euclidean {
+    fn weighted(wx: i32, wy: i32) -> Result<Self> {
+        check_weights(wx, wy)?;
+        Ok(Euclidean { wx, wy })
+    }
+}
+
+impl Metric for Euclidean {
+    fn length(&self, dx: i32, dy: i32) -> f64 {
+        let dx = i64::from(self.wx * dx);
+        let dy = i64::from(self.wy * dy);
+        ((dx * dx + dy * dy) as f64).sqrt()
+    }
+
+    /// A coordinate has an infinite area exactly when it is on the
+    /// boundary of the convex hull of the (weighted) coordinates. Those
+    /// in the middle of a hull edge own an infinite strip.
+    fn infinite_coords(&self, coords: &BTreeSet<Coord>, _bounds: &Bounds) -> BTreeSet<Coord> {
+        let scale = |&(x, y): &Coord| (i64::from(self.wx * x), i64::from(self.wy * y));
+        let hull = convex_hull(coords.iter().map(scale).collect());
+
+        coords
+            .iter()
+            .filter(|c| {
+                let p = scale(c);
+                match hull.len() {
+                    0 => false,
+                    1 => hull[0] == p,
+                    n => (0..n).any(|i| on_segment(hull[i], hull[(i + 1) % n], p)),
+                }
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+type Point = (i64, i64);
+
+fn cross(o: Point, a: Point, b: Point) -> i64 {
+    (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+}
+
+fn on_segment(a: Point, b: Point, p: Point) -> bool {
+    cross(a, b, p) == 0
+        && (a.0.min(b.0)..=a.0.max(b.0)).contains(&p.0)
+        && (a.1.min(b.1)..=a.1.max(b.1)).contains(&p.1)
+}
+
+/// The vertices of the convex hull in counter-clockwise order, without
+/// any points in the middle of an edge (monotone chain).
+fn convex_hull(mut points: Vec<Point>) -> Vec<Point> {
+    points.sort();
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+
+    let mut hull: Vec<Point> = Vec::with_capacity(2 * points.len());
+    for pass in 0..2 {
+        let start = hull.len();
+        let mut add = |p: Point| {
+            while let [.., a, b] = hull[start..] {
+                if cross(a, b, p) > 0 {
+                    break;
+                }
+                hull.pop();
+            }
+            hull.push(p);
+        };
+        if pass == 0 {
+            points.iter().cloned().for_each(&mut add);
+        } else {
+            points.iter().rev().cloned().for_each(&mut add);
+        }
+        // The last point of each half is the first point of the other
+        hull.pop();
+    }
+
+    hull
+}
+
+/// Finds the nearest coordinate for every cell in the bounds with a
+/// breadth-first search starting from all coordinates at once.
+///
+/// The coordinates nearest to a cell are exactly those nearest to its
+/// neighbors that are one step closer, so ties spread outward just
+/// like unique owners do. This only holds for unweighted Manhattan
+/// distance.
+fn voronoi(coords: &BTreeSet<Coord>, bounds: &Bounds) -> BTreeMap<Coord, Nearest> {
+    let index = |coord| bounds.index(coord);
+
+    let mut cells: Vec<Option<(i32, Nearest)>> = vec![None; bounds.width() * bounds.height()];
+    let mut queue = VecDeque::new();
+
+    for &coord in coords {
+        cells[index(coord)] = Some((0, Nearest::One(coord)));
+        queue.push_back(coord);
+    }
+
+    while let Some(coord @ (x, y)) = queue.pop_front() {
+        let (d, nearest) = cells[index(coord)].expect("Queued cell was not visited");
+
+        let neighbors = [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)];
+        for &neighbor in neighbors.iter().filter(|&&c| bounds.contains(c)) {
+            let cell = &mut cells[index(neighbor)];
+            match *cell {
+                None => {
+                    *cell = Some((d + 1, nearest));
+                    queue.push_back(neighbor);
+                }
+                Some((nd, n)) if nd == d + 1 && n != nearest => {
+                    *cell = Some((nd, Nearest::Multiple));
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    bounds
+        .inclusive_coords()
+        .map(|coord| {
+            let (_, nearest) = cells[index(coord)].expect("Cell was not reached");
+            (coord, nearest)
+        })
+        .collect()
+}
+
+fn unique_max_by_key(coords: &BTreeSet<Coord>, f: impl Fn(&Coord) -> i32) -> Option<Coord> {
+    let mut best = None;
+    let mut tied = false;
+
+    for &coord in coords {
+        let key = f(&coord);
+        match best {
+            Some((_, best_key)) if key < best_key => {}
+            Some((_, best_key)) if key == best_key => tied = true,
+            _ => {
+                best = Some((coord, key));
+                tied = false;
+            }
+        }
+    }
+
+    best.filter(|_| !tied).map(|(coord, _)| coord)
+}
+
+/// The weighted total distance from each position along an axis to all
+/// of the (sorted) positions, for every position where that is under
+/// `limit`. Totals can exceed an `i32` even when `limit` doesn't, so
+/// they are kept as `i64`.
+fn axis_distances(positions: &[i32], weight: i32, limit: i64) -> Vec<i64> {
+    let (first, last) = match (positions.first(), positions.last()) {
+        (Some(&first), Some(&last)) => (i64::from(first), i64::from(last)),
+        _ => return Vec::new(),
+    };
+
+    // Outside of the positions, every step away adds `n * weight` to the total
+    let n = positions.len() as i64;
+    let weight = i64::from(weight);
+    let reach = limit / (n * weight) + 1;
+    let start = first - reach;
+
+    let mut total: i64 = positions.iter().map(|&p| weight * (i64::from(p) - start)).sum();
+    let mut n_before = 0;
+    let mut distances = Vec::new();
+
+    for at in start..=last + reach {
+        if total < limit {
+            distances.push(total);
+        }
+
+        // Moving one step forward gets closer to every position ahead
+        // and further from every position at or behind this one
+        while positions.get(n_before as usize).is_some_and(|&p| i64::from(p) <= at) {
+            n_before += 1;
+        }
+        total += weight * (n_before - (n - n_before));
+    }
+
+    distances
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Cell {
+    Owned { owner: usize, infinite: bool, coordinate: bool },
+    Tied,
+}
+
+/// A picture of which coordinate owns each cell within the bounds
+#[derive(Debug, Clone)]
+struct Map {
+    width: usize,
+    height: usize,
+    cells: Vec<Cell>,
+    /// Cells in the safe region next to a cell outside of it
+    safe_edge: Vec<bool>,
+    palette: Vec<[u8; 3]>,
+}
+
+impl Map {
+    fn new(
+        metric: &dyn Metric,
+        coords: &BTreeSet<Coord>,
+        bounds: &Bounds,
+        grid: &BTreeMap<Coord, Nearest>,
+        infinite_coords: &BTreeSet<Coord>,
+        max_total_distance: f64,
+    ) -> Self {
+        let owners: BTreeMap<_, _> = coords.iter().enumerate().map(|(i, &c)| (c, i)).collect();
+
+        let cells = bounds
+            .inclusive_coords()
+            .map(|coord| match grid[&coord] {
+                Nearest::One(c) => Cell::Owned {
+                    owner: owners[&c],
+                    infinite: infinite_coords.contains(&c),
+                    coordinate: c == coord,
+                },
+                Nearest::Multiple => Cell::Tied,
+            })
+            .collect();
+
+        let is_safe = |coord| {
+            coords.iter().map(|&c| metric.distance(c, coord)).sum::<f64>() < max_total_distance
+        };
+        let safe_edge = bounds
+            .inclusive_coords()
+            .map(|(x, y)| {
+                let neighbors = [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)];
+                is_safe((x, y)) && !neighbors.iter().all(|&n| is_safe(n))
+            })
+            .collect();
+
+        Map {
+            width: bounds.width(),
+            height: bounds.height(),
+            cells,
+            safe_edge,
+            palette: palette(coords.len()),
+        }
+    }
+
+    fn color(&self, idx: usize) -> [u8; 3] {
+        if self.safe_edge[idx] {
+            return [255, 255, 255];
+        }
+
+        match self.cells[idx] {
+            Cell::Owned { owner, infinite, .. } => {
+                let color = self.palette[owner];
+                if infinite {
+                    // Dim the areas that don't count
+                    color.map(|c| c / 3)
+                } else {
+                    color
+                }
+            }
+            Cell::Tied => [96, 96, 96],
+        }
+    }
+
+    /// One character per cell with a 24-bit background color. The
+    /// coordinates themselves are `*`, ties are `.` and the edge of the
+    /// safe region is `#`.
+    fn to_ansi(&self) -> String {
+        let mut out = String::new();
+
+        for row in 0..self.height {
+            for idx in row * self.width..(row + 1) * self.width {
+                let [r, g, b] = self.color(idx);
+                let c = match self.cells[idx] {
+                    _ if self.safe_edge[idx] => '#',
+                    Cell::Owned { coordinate: true, .. } => '*',
+                    Cell::Owned { .. } => ' ',
+                    Cell::Tied => '.',
+                };
+                write!(out, "\x1b[48;2;{};{};{}m\x1b[30m{}", r, g, b, c).unwrap();
+            }
+            out.push_str("\x1b[0m\n");
+        }
+
+        out
+    }
+
+    /// A binary PPM image with one pixel per cell
+    fn write_ppm(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        for idx in 0..self.cells.len() {
+            let color = match self.cells[idx] {
+                Cell::Owned { coordinate: true, .. } => [0, 0, 0],
+                _ => self.color(idx),
+            };
+            out.write_all(&color)?;
+        }
+        out.flush()
+    }
+}
+
+/// Spreads `n` colors around the hue wheel so that neighbors in the list
+/// look different from each other.
+fn palette(n: usize) -> Vec<[u8; 3]> {
+    const GOLDEN_RATIO_CONJUGATE: f64 = 0.618_033_988_749_895;
+
+    (0..n)
+        .map(|i| {
+            let hue = (i as f64 * GOLDEN_RATIO_CONJUGATE).fract() * 6.0;
+            let value = if i % 2 == 0 { 1.0 } else { 0.75 };
+            let x = 1.0 - (hue % 2.0 - 1.0).abs();
+            let (r, g, b) = match hue as u32 {
+                0 => (1.0, x, 0.0),
+                1 => (x, 1.0, 0.0),
+                2 => (0.0, 1.0, x),
+                3 => (0.0, x, 1.0),
+                4 => (x, 0.0, 1.0),
+                _ => (1.0, 0.0, x),
+            };
+            [r, g, b].map(|c: f64| (c * value * 255.0).round() as u8)
+        })
+        .collect()
+}
+
+/// A rectangle of cells. Both the minimum and maximum of each axis are
+/// inside the rectangle.
 #[derive(Debug, Copy, Clone, PartialEq)]
 struct Bounds {
     min_x: i32,
@@ -95,6 +699,7 @@ struct Bounds {
 }
 
 impl Bounds {
+    /// The smallest bounds containing every coordinate
     fn new<'a>(coords: impl IntoIterator<Item = &'a Coord> + Copy) -> Result<Bounds> {
         let (&min_x, &max_x) = coords
             .into_iter()
@@ -111,34 +716,68 @@ impl Bounds {
         Ok(Bounds { min_x, max_x, min_y, max_y })
     }
 
-    fn grid_coords(&self) -> impl Iterator<Item = Coord> {
-        let Bounds { min_x, max_x, min_y, max_y } = *self;
-        (min_x..max_x).cartesian_product(min_y..max_y)
+    fn width(&self) -> usize {
+        (self.max_x - self.min_x + 1) as usize
     }
 
-    fn fringe_coords(&self) -> impl Iterator<Item = Coord> {
-        let Bounds { mut min_x, mut max_x, mut min_y, mut max_y } = *self;
-        min_x -= 1;
-        min_y -= 1;
-        max_x += 1;
-        max_y += 1;
+    fn height(&self) -> usize {
+        (self.max_y - self.min_y + 1) as usize
+    }
+
+    fn contains(&self, (x, y): Coord) -> bool {
+        (self.min_x..=self.max_x).contains(&x) && (self.min_y..=self.max_y).contains(&y)
+    }
 
-        let top = (min_x..max_x).map(move |x| (x, min_y));
-        let bot = (min_x..max_x).map(move |x| (x, max_y));
-        let lft = (min_y..max_y).map(move |y| (min_x, y));
-        let rgt = (min_y..max_y).map(move |y| (max_x, y));
+    /// The position of a contained cell when stored row by row
+    fn index(&self, (x, y): Coord) -> usize {
+        (y - self.min_y) as usize * self.width() + (x - self.min_x) as usize
+    }
+
+    /// Grows each side outward by the given number of cells
+    fn expand(&self, dx: i32, dy: i32) -> Bounds {
+        Bounds {
+            min_x: self.min_x - dx,
+            max_x: self.max_x + dx,
+            min_y: self.min_y - dy,
+            max_y: self.max_y + dy,
+        }
+    }
+
+    /// Every cell, including the edges, row by row
+    fn inclusive_coords(&self) -> impl Iterator<Item = Coord> {
+        let Bounds { min_x, max_x, min_y, max_y } = *self;
+        (min_y..=max_y).flat_map(move |y| (min_x..=max_x).map(move |x| (x, y)))
+    }
+
+    /// Every cell that is not on an edge, row by row
+    #[cfg(test)]
+    fn interior_coords(&self) -> impl Iterator<Item = Coord> {
+        let Bounds { min_x, max_x, min_y, max_y } = *self;
+        (min_y + 1..max_y).flat_map(move |y| (min_x + 1..max_x).map(move |x| (x, y)))
+    }
 
-        top.chain(bot).chain(lft).chain(rgt)
+    /// Every cell on an edge, including the corners, each exactly once
+    #[cfg(test)]
+    fn edge_coords(&self) -> impl Iterator<Item = Coord> {
+        self.ring_coords(0)
     }
 
-    fn wide_coords(&self) -> impl Iterator<Item = Coord> {
-        let Bounds { mut min_x, mut max_x, mut min_y, mut max_y } = *self;
-        min_x -= 10_000;
-        min_y -= 10_000;
-        max_x += 10_000;
-        max_y += 10_000;
+    /// Every cell exactly `k` steps outside of the bounds, counting
+    /// diagonal steps, row by row. Each cell, including the corners,
+    /// appears once.
+    #[cfg(test)]
+    fn ring_coords(&self, k: u32) -> impl Iterator<Item = Coord> {
+        let k = k as i32;
+        let Bounds { min_x, max_x, min_y, max_y } = self.expand(k, k);
+
+        // A ring one cell wide or tall is just a line of cells
+        let thin = min_x == max_x || min_y == max_y;
 
-        (min_x..max_x).cartesian_product(min_y..max_y)
+        (min_y..=max_y).flat_map(move |y| {
+            let whole_row = thin || y == min_y || y == max_y;
+            let step = if whole_row { 1 } else { (max_x - min_x) as usize };
+            (min_x..=max_x).step_by(step).map(move |x| (x, y))
+        })
     }
 }
 
@@ -154,3 +793,320 @@ fn coords() -> Result<BTreeSet<Coord>> {
         })
         .collect()
 }
+
+#[cfg(test)]
+fn example() -> BTreeSet<Coord> {
+    [(1, 1), (1, 6), (8, 3), (3, 4), (5, 5), (8, 9)].iter().cloned().collect()
+}
+
+/// Deterministic pseudo-random coordinates, many of them collinear
+#[cfg(test)]
+fn scattered(seed: u64, n: usize, size: i32) -> BTreeSet<Coord> {
+    let mut state = seed;
+    let mut next = || {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        ((state >> 33) % size as u64) as i32
+    };
+    (0..n).map(|_| (next(), next())).collect()
+}
+
+/// Uses the straightforward default implementations, finding infinite
+/// areas by looking at cells far outside of the bounds.
+#[cfg(test)]
+struct BruteForce<M>(M);
+
+#[cfg(test)]
+impl<M: Metric + ?Sized> Metric for BruteForce<&M> {
+    fn length(&self, dx: i32, dy: i32) -> f64 {
+        self.0.length(dx, dy)
+    }
+
+    fn infinite_coords(&self, coords: &BTreeSet<Coord>, bounds: &Bounds) -> BTreeSet<Coord> {
+        // Far enough away, ownership along each ray has settled
+        bounds
+            .ring_coords(500)
+            .filter_map(|c| find_nearest(self, coords, c).into_option())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+fn metrics() -> Vec<Box<dyn Metric>> {
+    vec![
+        Box::new(Manhattan::default()),
+        Box::new(Manhattan::weighted(2, 3).unwrap()),
+        Box::new(Chebyshev::default()),
+        Box::new(Chebyshev::weighted(2, 4).unwrap()),
+        Box::new(Euclidean::default()),
+        Box::new(Euclidean::weighted(3, 1).unwrap()),
+    ]
+}
+
+#[test]
+fn manhattan_answers() {
+    let metric = Manhattan::default();
+
+    let example = example();
+    let bounds = Bounds::new(&example).unwrap();
+    let grid = metric.voronoi(&example, &bounds);
+    let infinite = metric.infinite_coords(&example, &bounds);
+    assert_eq!(largest_finite_area(&grid, &infinite), Some(((5, 5), 17)));
+    assert_eq!(metric.safe_region_size(&example, 32.0), 16);
+
+    let input = coords().unwrap();
+    let bounds = Bounds::new(&input).unwrap();
+    let grid = metric.voronoi(&input, &bounds);
+    let infinite = metric.infinite_coords(&input, &bounds);
+    assert_eq!(largest_finite_area(&grid, &infinite), Some(((102, 101), 3251)));
+    assert_eq!(metric.safe_region_size(&input, MAX_TOTAL_DISTANCE), 47841);
+}
+
+#[test]
+fn voronoi_matches_brute_force() {
+    let mut cases = vec![example(), coords().unwrap()];
+    cases.extend((0..20).map(|seed| scattered(seed, 2 + seed as usize, 15)));
+
+    let metric = Manhattan::default();
+    for coords in cases {
+        let bounds = Bounds::new(&coords).unwrap();
+        let grid = metric.voronoi(&coords, &bounds);
+
+        for coord in bounds.inclusive_coords() {
+            assert_eq!(grid[&coord], find_nearest(&metric, &coords, coord), "{:?}", coord);
+        }
+    }
+}
+
+#[test]
+fn infinite_coords_example() {
+    let coords = example();
+    let bounds = Bounds::new(&coords).unwrap();
+    let expected = [(1, 1), (1, 6), (8, 3), (8, 9)].iter().cloned().collect();
+
+    assert_eq!(Manhattan::default().infinite_coords(&coords, &bounds), expected);
+}
+
+#[test]
+fn infinite_coords_matches_brute_force() {
+    for (i, metric) in metrics().into_iter().enumerate() {
+        for seed in 0..40 {
+            let coords = scattered(seed, 1 + seed as usize % 12, 10);
+            let bounds = Bounds::new(&coords).unwrap();
+
+            assert_eq!(
+                metric.infinite_coords(&coords, &bounds),
+                BruteForce(&*metric).infinite_coords(&coords, &bounds),
+                "metric {} with {:?}",
+                i,
+                coords,
+            );
+        }
+    }
+}
+
+#[test]
+fn infinite_coords_matches_fringe() {
+    let coords = coords().unwrap();
+    let bounds = Bounds::new(&coords).unwrap();
+    let metric = Manhattan::default();
+
+    // Checking one cell outside the bounds agrees
+    let fringe: BTreeSet<_> = bounds
+        .ring_coords(1)
+        .filter_map(|c| find_nearest(&metric, &coords, c).into_option())
+        .collect();
+    assert_eq!(metric.infinite_coords(&coords, &bounds), fringe);
+}
+
+#[test]
+fn manhattan_safe_region_matches_brute_force() {
+    for seed in 0..30 {
+        let coords = scattered(seed, 1 + seed as usize % 7, 12);
+        for metric in &[Manhattan::default(), Manhattan::weighted(3, 2).unwrap()] {
+            for &limit in &[0.0, 1.0, 5.0, 17.0, 40.5, 100.0] {
+                assert_eq!(
+                    metric.safe_region_size(&coords, limit),
+                    BruteForce(metric).safe_region_size(&coords, limit),
+                    "{:?} within {} using {:?}",
+                    coords,
+                    limit,
+                    metric,
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn axis_distances_near_the_largest_threshold() {
+    // The totals at the ends of the range are larger than an `i32`
+    let (positions, weight, limit) = ([0, 1000], 1_000_000, i64::from(i32::MAX));
+    let distances = axis_distances(&positions, weight, limit);
+
+    let total = |at: i64| -> i64 {
+        positions.iter().map(|&p| i64::from(weight) * (i64::from(p) - at).abs()).sum()
+    };
+    let expected: Vec<_> = (-5000..6000).map(total).filter(|&t| t < limit).collect();
+    assert_eq!(distances, expected);
+
+    assert!(check_threshold(f64::from(i32::MAX)).is_ok());
+    for &bad in &[f64::INFINITY, f64::NAN, 1e10, -1.0] {
+        assert!(check_threshold(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn other_metrics() {
+    let coords = example();
+    let bounds = Bounds::new(&coords).unwrap();
+
+    let metric = Chebyshev::default();
+    assert_eq!(metric.distance((1, 1), (4, 3)), 3.0);
+    let grid = metric.voronoi(&coords, &bounds);
+    assert_eq!(grid[&(5, 4)], Nearest::One((5, 5)));
+
+    let metric = Euclidean::default();
+    assert_eq!(metric.distance((1, 1), (4, 5)), 5.0);
+    let expected = [(1, 1), (1, 6), (8, 3), (8, 9)].iter().cloned().collect();
+    assert_eq!(metric.infinite_coords(&coords, &bounds), expected);
+
+    // Points in the middle of a hull edge own an infinite strip
+    let line = [(0, 0), (2, 0), (4, 0), (2, 2)].iter().cloned().collect();
+    let bounds = Bounds::new(&line).unwrap();
+    assert_eq!(metric.infinite_coords(&line, &bounds), line);
+}
+
+#[test]
+fn map_example() {
+    let metric = Manhattan::default();
+    let coords = example();
+    let bounds = Bounds::new(&coords).unwrap();
+    let grid = metric.voronoi(&coords, &bounds);
+    let infinite = metric.infinite_coords(&coords, &bounds);
+    let map = Map::new(&metric, &coords, &bounds, &grid, &infinite, 32.0);
+
+    assert_eq!((map.width, map.height), (8, 9));
+
+    // (3, 4) is D from the puzzle, which has a finite area
+    let d = 3 * map.width + 2;
+    assert_eq!(map.cells[d], Cell::Owned { owner: 2, infinite: false, coordinate: true });
+    // (5, 1) is tied between A and C
+    assert_eq!(map.cells[4], Cell::Tied);
+    assert!(!map.safe_edge[d]);
+    assert_eq!(map.safe_edge.iter().filter(|&&e| e).count(), 10);
+
+    let mut ppm = Vec::new();
+    map.write_ppm(&mut ppm).unwrap();
+    assert!(ppm.starts_with(b"P6\n8 9\n255\n"));
+    assert_eq!(ppm.len(), b"P6\n8 9\n255\n".len() + 8 * 9 * 3);
+
+    assert_eq!(map.to_ansi().lines().count(), 9);
+}
+
+#[cfg(test)]
+fn assert_unique(coords: impl Iterator<Item = Coord>) -> BTreeSet<Coord> {
+    let coords: Vec<_> = coords.collect();
+    let unique: BTreeSet<_> = coords.iter().cloned().collect();
+    assert_eq!(coords.len(), unique.len(), "Duplicate cells in {:?}", coords);
+    unique
+}
+
+#[cfg(test)]
+fn check_bounds(bounds: Bounds) {
+    let all = assert_unique(bounds.inclusive_coords());
+    let interior = assert_unique(bounds.interior_coords());
+    let edge = assert_unique(bounds.edge_coords());
+
+    assert_eq!(all.len(), bounds.width() * bounds.height());
+    assert!(all.iter().all(|&c| bounds.contains(c)));
+    assert!(interior.is_disjoint(&edge));
+    assert_eq!(&interior | &edge, all);
+
+    for (i, coord) in bounds.inclusive_coords().enumerate() {
+        assert_eq!(bounds.index(coord), i);
+    }
+
+    let mut seen = all;
+    for k in 1..4 {
+        let ring = assert_unique(bounds.ring_coords(k));
+        let expanded = bounds.expand(k as i32, k as i32);
+
+        assert!(seen.is_disjoint(&ring), "ring {} overlaps for {:?}", k, bounds);
+        seen.extend(ring);
+        assert_eq!(seen, expanded.inclusive_coords().collect(), "ring {} for {:?}", k, bounds);
+    }
+}
+
+#[test]
+fn bounds_single_point() {
+    let coords: BTreeSet<_> = [(3, -2)].iter().cloned().collect();
+    let bounds = Bounds::new(&coords).unwrap();
+
+    assert_eq!(bounds, Bounds { min_x: 3, max_x: 3, min_y: -2, max_y: -2 });
+    assert_eq!((bounds.width(), bounds.height()), (1, 1));
+    assert_eq!(bounds.inclusive_coords().collect::<Vec<_>>(), [(3, -2)]);
+    assert_eq!(bounds.interior_coords().count(), 0);
+    assert_eq!(bounds.edge_coords().collect::<Vec<_>>(), [(3, -2)]);
+    assert_eq!(bounds.ring_coords(1).count(), 8);
+    assert_eq!(bounds.ring_coords(5).count(), 40);
+    check_bounds(bounds);
+}
+
+#[test]
+fn bounds_collinear() {
+    let row: BTreeSet<_> = [(0, 4), (3, 4), (7, 4)].iter().cloned().collect();
+    let bounds = Bounds::new(&row).unwrap();
+    assert_eq!((bounds.width(), bounds.height()), (8, 1));
+    assert_eq!(bounds.interior_coords().count(), 0);
+    assert_eq!(bounds.edge_coords().count(), 8);
+    assert_eq!(bounds.ring_coords(1).count(), 10 * 3 - 8);
+    check_bounds(bounds);
+
+    let column: BTreeSet<_> = [(2, 0), (2, 1)].iter().cloned().collect();
+    let bounds = Bounds::new(&column).unwrap();
+    assert_eq!((bounds.width(), bounds.height()), (1, 2));
+    assert_eq!(bounds.edge_coords().count(), 2);
+    check_bounds(bounds);
+
+    let diagonal: BTreeSet<_> = [(0, 0), (1, 1), (2, 2)].iter().cloned().collect();
+    let bounds = Bounds::new(&diagonal).unwrap();
+    assert_eq!(bounds.interior_coords().collect::<Vec<_>>(), [(1, 1)]);
+    check_bounds(bounds);
+}
+
+#[test]
+fn bounds_rectangles() {
+    assert!(Bounds::new(&BTreeSet::new()).is_err());
+
+    for seed in 0..20 {
+        let coords = scattered(seed, 1 + seed as usize % 5, 8);
+        check_bounds(Bounds::new(&coords).unwrap());
+    }
+
+    // The maximum row and column contain coordinates, so they're included
+    let bounds = Bounds::new(&example()).unwrap();
+    assert!(bounds.inclusive_coords().any(|c| c == (8, 9)));
+    assert_eq!(bounds.edge_coords().count(), 2 * 8 + 2 * 9 - 4);
+}
+
+#[test]
+fn degenerate_regions() {
+    let metric = Manhattan::default();
+
+    let point: BTreeSet<_> = [(3, 3)].iter().cloned().collect();
+    let bounds = Bounds::new(&point).unwrap();
+    let grid = metric.voronoi(&point, &bounds);
+    let infinite = metric.infinite_coords(&point, &bounds);
+    assert_eq!(infinite, point);
+    assert_eq!(largest_finite_area(&grid, &infinite), None);
+    // A diamond of radius 4
+    assert_eq!(metric.safe_region_size(&point, 5.0), 2 * 25 - 2 * 5 + 1);
+
+    let line: BTreeSet<_> = [(0, 0), (2, 0), (4, 0)].iter().cloned().collect();
+    let bounds = Bounds::new(&line).unwrap();
+    let grid = metric.voronoi(&line, &bounds);
+    assert_eq!(grid[&(1, 0)], Nearest::Multiple);
+    assert_eq!(metric.infinite_coords(&line, &bounds), line);
+    let brute_force = BruteForce(&metric).safe_region_size(&line, 5.0);
+    assert_eq!(metric.safe_region_size(&line, 5.0), brute_force);
+}
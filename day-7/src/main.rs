@@ -1,17 +1,94 @@
 use regex::Regex;
-use std::collections::{BTreeMap, BTreeSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, VecDeque};
+use std::{fmt, fs};
 
 static INPUT: &str = include_str!("../input.txt");
 const STEP_DURATION_BASE: u32 = 60;
 const N_WORKERS: usize = 5;
+/// The Gantt chart is scaled down to fit in this many columns
+const GANTT_COLUMNS: u32 = 100;
 
-type Error = Box<std::error::Error>;
+type Error = Box<dyn std::error::Error>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Each step and the steps that must be finished before it can begin
+type Graph<'a> = BTreeMap<&'a str, BTreeSet<&'a str>>;
+
 fn main() -> Result<()> {
-    let mut graph = BTreeMap::new();
+    let mut durations = Durations::new(STEP_DURATION_BASE);
+    let mut workers = vec![Worker::any(); N_WORKERS];
+    let mut table = false;
+    let mut gantt = false;
+    let mut svg = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--base" => durations.base = args.next().ok_or("Missing base duration")?.parse()?,
+            "--durations" => {
+                let path = args.next().ok_or("Missing durations path")?;
+                durations.parse_table(&fs::read_to_string(path)?)?;
+            }
+            "--workers" => {
+                let workers_arg = args.next().ok_or("Missing worker count or path")?;
+                workers = match workers_arg.parse() {
+                    Ok(n_workers) => vec![Worker::any(); n_workers],
+                    Err(_) => parse_workers(&fs::read_to_string(workers_arg)?)?,
+                };
+            }
+            "--table" => table = true,
+            "--gantt" => gantt = true,
+            "--svg" => svg = Some(args.next().ok_or("Missing SVG path")?),
+            _ => return Err(format!("Unknown argument {}", arg).into()),
+        }
+    }
+
+    let dependencies = dependencies(INPUT)?;
+
+    let problems = diagnose(&dependencies);
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if problems.iter().any(Problem::is_fatal) {
+        return Err(Error::from("The steps can never all be finished"));
+    }
+
+    let graph = graph(dependencies);
+
+    let order = topological_order(&graph);
+    println!("The order is '{}'", format_order(&order));
+
+    check_schedule(&graph, &workers, &durations)?;
+    let schedule = schedule(&graph, &workers, |step| durations.get(step).unwrap());
+    println!(
+        "With {} workers, the order is '{}'",
+        workers.len(),
+        format_order(&schedule.order)
+    );
+    println!("It took {} seconds", schedule.time);
+
+    let critical_path = critical_path(&graph, |step| durations.get(step).unwrap());
+    println!("The critical path is {}", critical_path.steps.join(" -> "));
+    println!("Even unlimited workers would take {} seconds", critical_path.time);
+
+    if table {
+        print!("{}", schedule.table());
+    }
+    if gantt {
+        let scale = schedule.time.div_ceil(GANTT_COLUMNS).max(1);
+        println!("Each column is {} seconds", scale);
+        print!("{}", schedule.gantt(scale));
+    }
+    if let Some(path) = svg {
+        fs::write(path, schedule.svg())?;
+    }
 
-    let dependencies = dependencies()?;
+    Ok(())
+}
+
+fn graph<'a>(dependencies: impl IntoIterator<Item = (&'a str, &'a str)>) -> Graph<'a> {
+    let mut graph = BTreeMap::new();
 
     for (bef, aft) in dependencies {
         graph.entry(aft).or_insert_with(BTreeSet::new).insert(bef);
@@ -31,57 +108,598 @@ fn main() -> Result<()> {
         }
     }
 
-    let mut order: Vec<_> = Vec::new();
+    graph
+}
+
+/// The order a single worker completes the steps in, choosing the
+/// alphabetically first step whenever more than one is available.
+fn topological_order<'a>(graph: &Graph<'a>) -> Vec<&'a str> {
+    let mut graph = graph.clone();
+    let mut order = Vec::with_capacity(graph.len());
+
+    let mut available: BTreeSet<_> = graph
+        .iter()
+        .filter(|(_, bef)| bef.is_empty())
+        .map(|(&aft, _)| aft)
+        .collect();
+
+    while let Some(&name) = available.iter().next() {
+        available.remove(name);
+        graph.remove(name);
+        order.push(name);
+
+        for (&aft, bef) in &mut graph {
+            if bef.remove(name) && bef.is_empty() {
+                available.insert(aft);
+            }
+        }
+    }
+
+    order
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Problem<'a> {
+    /// A step that must be finished before itself
+    SelfDependency(&'a str),
+    /// The same dependency listed more than once
+    DuplicateDependency { bef: &'a str, aft: &'a str, count: usize },
+    /// Steps that each wait on the previous one, ending where it started
+    Cycle(Vec<&'a str>),
+}
+
+impl Problem<'_> {
+    /// Whether this prevents the steps from ever being finished
+    fn is_fatal(&self) -> bool {
+        match self {
+            Problem::SelfDependency(_) | Problem::Cycle(_) => true,
+            Problem::DuplicateDependency { .. } => false,
+        }
+    }
+}
+
+impl fmt::Display for Problem<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::SelfDependency(step) => {
+                write!(f, "Error: step {} must be finished before itself", step)
+            }
+            Problem::DuplicateDependency { bef, aft, count } => write!(
+                f,
+                "Warning: step {} must be finished before step {} is listed {} times",
+                bef, aft, count
+            ),
+            Problem::Cycle(steps) => {
+                write!(f, "Error: steps depend on each other: {}", steps.join(" -> "))
+            }
+        }
+    }
+}
+
+/// Finds anything that looks wrong with the dependencies, reporting one
+/// cycle for each group of steps that wait on each other.
+fn diagnose<'a>(dependencies: &[(&'a str, &'a str)]) -> Vec<Problem<'a>> {
+    let mut problems = Vec::new();
+
+    let mut counts = BTreeMap::new();
+    for &(bef, aft) in dependencies {
+        *counts.entry((bef, aft)).or_insert(0) += 1;
+    }
+
+    let mut edges: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+    for (&(bef, aft), &count) in &counts {
+        if bef == aft {
+            problems.push(Problem::SelfDependency(bef));
+        } else {
+            edges.entry(bef).or_default().insert(aft);
+        }
+
+        if count > 1 {
+            problems.push(Problem::DuplicateDependency { bef, aft, count });
+        }
+    }
+
+    for component in strongly_connected_components(&edges) {
+        if component.len() > 1 {
+            problems.push(Problem::Cycle(cycle_through(&edges, &component)));
+        }
+    }
+
+    problems
+}
+
+/// Tarjan's algorithm over the `before -> after` edges. A chain of
+/// steps can be arbitrarily long, so the depth-first search keeps its
+/// own stack instead of recursing.
+fn strongly_connected_components<'a>(
+    edges: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+) -> Vec<BTreeSet<&'a str>> {
+    #[derive(Default)]
+    struct State<'a> {
+        next_index: usize,
+        index: BTreeMap<&'a str, usize>,
+        low_link: BTreeMap<&'a str, usize>,
+        stack: Vec<&'a str>,
+        on_stack: BTreeSet<&'a str>,
+        components: Vec<BTreeSet<&'a str>>,
+    }
+
+    impl<'a> State<'a> {
+        fn enter(&mut self, step: &'a str) {
+            self.index.insert(step, self.next_index);
+            self.low_link.insert(step, self.next_index);
+            self.next_index += 1;
+            self.stack.push(step);
+            self.on_stack.insert(step);
+        }
+
+        fn lower(&mut self, step: &'a str, low: usize) {
+            let low = usize::min(self.low_link[step], low);
+            self.low_link.insert(step, low);
+        }
+
+        fn leave(&mut self, step: &'a str) {
+            if self.low_link[step] == self.index[step] {
+                let mut component = BTreeSet::new();
+                while let Some(member) = self.stack.pop() {
+                    self.on_stack.remove(member);
+                    component.insert(member);
+                    if member == step {
+                        break;
+                    }
+                }
+                self.components.push(component);
+            }
+        }
+    }
+
+    let no_edges = BTreeSet::new();
+    let successors = |step| edges.get(step).unwrap_or(&no_edges).iter();
+
+    let mut state = State::default();
+    // Each step being visited, with the successors it hasn't looked at yet
+    let mut visiting = Vec::new();
+
+    for &root in edges.keys() {
+        if state.index.contains_key(root) {
+            continue;
+        }
+        state.enter(root);
+        visiting.push((root, successors(root)));
+
+        while let Some((step, next_steps)) = visiting.last_mut() {
+            let step = *step;
+            match next_steps.next() {
+                Some(&next) if !state.index.contains_key(next) => {
+                    state.enter(next);
+                    visiting.push((next, successors(next)));
+                }
+                Some(&next) => {
+                    if state.on_stack.contains(next) {
+                        let index = state.index[next];
+                        state.lower(step, index);
+                    }
+                }
+                None => {
+                    visiting.pop();
+                    state.leave(step);
+                    if let Some(&(parent, _)) = visiting.last() {
+                        let low = state.low_link[step];
+                        state.lower(parent, low);
+                    }
+                }
+            }
+        }
+    }
+
+    state.components.sort();
+    state.components
+}
+
+/// The shortest cycle starting and ending at the first step of a
+/// strongly connected component.
+fn cycle_through<'a>(
+    edges: &BTreeMap<&'a str, BTreeSet<&'a str>>,
+    component: &BTreeSet<&'a str>,
+) -> Vec<&'a str> {
+    let start = *component.iter().next().expect("Component is empty");
+
+    let mut came_from = BTreeMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+
+    while let Some(step) = queue.pop_front() {
+        for &next in edges.get(step).into_iter().flatten() {
+            if !component.contains(next) || came_from.contains_key(next) {
+                continue;
+            }
+            came_from.insert(next, step);
+
+            if next == start {
+                let mut cycle = vec![start];
+                let mut at = step;
+                while at != start {
+                    cycle.push(at);
+                    at = came_from[at];
+                }
+                cycle.push(start);
+                cycle.reverse();
+                return cycle;
+            }
+
+            queue.push_back(next);
+        }
+    }
+
+    unreachable!("Every step in a component can reach every other step")
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Schedule<'a> {
+    /// The order that the steps are finished in
+    order: Vec<&'a str>,
+    /// Seconds until every step is finished
+    time: u32,
+    /// Every step that was worked on, ordered by start time then worker
+    tasks: Vec<Task<'a>>,
+    /// How many workers there were, including any that stayed idle
+    n_workers: usize,
+}
+
+/// A worker executing a step from `start` up to, but not including, `end`
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Task<'a> {
+    worker: usize,
+    step: &'a str,
+    start: u32,
+    end: u32,
+}
+
+impl<'a> Schedule<'a> {
+    /// The step each worker is executing during `second`
+    fn working_on(&self, second: u32) -> Vec<Option<&'a str>> {
+        let mut steps = vec![None; self.n_workers];
+        for task in &self.tasks {
+            if task.start <= second && second < task.end {
+                steps[task.worker] = Some(task.step);
+            }
+        }
+        steps
+    }
+
+    /// The second-by-second table from the puzzle description
+    fn table(&self) -> String {
+        let headers: Vec<_> = (1..=self.n_workers).map(|n| format!("Worker {}", n)).collect();
+        let width = headers
+            .iter()
+            .map(String::len)
+            .chain(self.tasks.iter().map(|task| task.step.len()))
+            .max()
+            .unwrap_or(0);
+
+        let headers: Vec<_> = headers.iter().map(|h| format!("{:^1$}", h, width)).collect();
+        let mut out = format!("Second   {}   Done\n", headers.join("   "));
+
+        for second in 0..=self.time {
+            let mut line = format!("{:>4}  ", second);
+            for step in self.working_on(second) {
+                line.push_str(&format!("   {:^1$}", step.unwrap_or("."), width));
+            }
+
+            let n_done = self.tasks.iter().filter(|task| task.end <= second).count();
+            line.push_str("   ");
+            line.push_str(&format_order(&self.order[..n_done]));
+
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+
+        out
+    }
+
+    /// One row per worker, with each column covering `scale` seconds.
+    /// Steps are labelled at their start and idle time is left blank.
+    fn gantt(&self, scale: u32) -> String {
+        assert!(scale > 0, "Each column must cover some time");
+
+        let columns = self.time.div_ceil(scale) as usize;
+        let mut rows = vec![vec![' '; columns]; self.n_workers];
+
+        for task in &self.tasks {
+            let first = (task.start / scale) as usize;
+            let last = ((task.end - 1) / scale) as usize;
+            let label = task.step.chars().chain(std::iter::repeat('-'));
+
+            for (cell, c) in rows[task.worker][first..=last].iter_mut().zip(label) {
+                *cell = c;
+            }
+        }
+
+        let label_width = format!("Worker {}", self.n_workers).len();
+        let mut out = String::new();
+
+        for (worker, row) in rows.iter().enumerate() {
+            let label = format!("Worker {}", worker + 1);
+            let row: String = row.iter().collect();
+            out.push_str(&format!("{:<2$} |{}|\n", label, row, label_width));
+        }
+
+        let mut axis = String::new();
+        for column in (0..=columns).step_by(10) {
+            let tick = (column as u32 * scale).to_string();
+            if axis.len() <= column {
+                axis.push_str(&" ".repeat(column - axis.len()));
+                axis.push_str(&tick);
+            }
+        }
+        out.push_str(&format!("{:<2$}  {}\n", "", axis, label_width));
+
+        out
+    }
+
+    /// A timeline with one row per worker and a coloured bar per step
+    fn svg(&self) -> String {
+        const SECOND_WIDTH: u32 = 10;
+        const ROW_HEIGHT: u32 = 30;
+        const LABEL_WIDTH: u32 = 80;
+
+        let width = LABEL_WIDTH + self.time * SECOND_WIDTH + SECOND_WIDTH;
+        let height = (self.n_workers as u32 + 1) * ROW_HEIGHT;
+        let golden_ratio_conjugate = 0.618_034;
+
+        let mut out = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" \
+             font-family=\"monospace\" font-size=\"12\">\n",
+            width, height
+        );
+
+        for worker in 0..self.n_workers {
+            let y = worker as u32 * ROW_HEIGHT;
+            out.push_str(&format!(
+                "<text x=\"4\" y=\"{}\">Worker {}</text>\n",
+                y + ROW_HEIGHT / 2 + 4,
+                worker + 1
+            ));
+        }
+
+        for task in &self.tasks {
+            let index = self.order.iter().position(|&step| step == task.step).unwrap_or(0);
+            let hue = (index as f64 * golden_ratio_conjugate).fract() * 360.0;
+            let x = LABEL_WIDTH + task.start * SECOND_WIDTH;
+            let y = task.worker as u32 * ROW_HEIGHT + 2;
+            let w = (task.end - task.start) * SECOND_WIDTH;
+
+            out.push_str(&format!(
+                "<g><title>{step}: {start}s to {end}s</title>\
+                 <rect x=\"{x}\" y=\"{y}\" width=\"{w}\" height=\"{h}\" \
+                 fill=\"hsl({hue:.0}, 60%, 70%)\" stroke=\"black\"/>\
+                 <text x=\"{tx}\" y=\"{ty}\" text-anchor=\"middle\">{step}</text></g>\n",
+                step = task.step,
+                start = task.start,
+                end = task.end,
+                x = x,
+                y = y,
+                w = w,
+                h = ROW_HEIGHT - 4,
+                hue = hue,
+                tx = x + w / 2,
+                ty = y + ROW_HEIGHT / 2 + 2,
+            ));
+        }
+
+        let axis_y = self.n_workers as u32 * ROW_HEIGHT;
+        let tick_every = (self.time / 10).max(1);
+        for second in (0..=self.time).step_by(tick_every as usize) {
+            let x = LABEL_WIDTH + second * SECOND_WIDTH;
+            out.push_str(&format!(
+                "<line x1=\"{x}\" y1=\"0\" x2=\"{x}\" y2=\"{y}\" stroke=\"grey\" \
+                 stroke-dasharray=\"2\"/><text x=\"{x}\" y=\"{ty}\" \
+                 text-anchor=\"middle\">{s}</text>\n",
+                x = x,
+                y = axis_y,
+                ty = axis_y + ROW_HEIGHT / 2,
+                s = second,
+            ));
+        }
+
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+/// Simulates the workers working together, each starting the
+/// alphabetically first available step it can execute whenever it is
+/// idle. Use `check_schedule` first; steps nobody can execute are never
+/// finished.
+///
+/// Time jumps straight to the next step being finished, so this doesn't
+/// depend on how long the steps take.
+fn schedule<'a>(
+    graph: &Graph<'a>,
+    workers: &[Worker],
+    duration: impl Fn(&str) -> u32,
+) -> Schedule<'a> {
+    assert!(!workers.is_empty(), "Must have at least one worker");
+
+    let mut waiting_on: BTreeMap<_, _> = graph.iter().map(|(&aft, bef)| (aft, bef.len())).collect();
+    let mut dependents: BTreeMap<_, Vec<_>> = BTreeMap::new();
+    for (&aft, bef) in graph {
+        for &bef in bef {
+            dependents.entry(bef).or_default().push(aft);
+        }
+    }
+
+    let mut available: BTreeSet<_> =
+        waiting_on.iter().filter(|&(_, &n)| n == 0).map(|(&step, _)| step).collect();
+    let mut running: Vec<Option<(&str, u32)>> = vec![None; workers.len()];
+    let mut finishing = BinaryHeap::new();
+
+    let mut order = Vec::with_capacity(graph.len());
+    let mut tasks = Vec::with_capacity(graph.len());
+    let mut time = 0;
+
+    loop {
+        for (index, (worker, slot)) in workers.iter().zip(&mut running).enumerate() {
+            if slot.is_some() {
+                continue;
+            }
+
+            if let Some(&step) = available.iter().find(|&&step| worker.can_execute(step)) {
+                available.remove(step);
+                *slot = Some((step, time));
+                finishing.push(Reverse((time + duration(step).max(1), index)));
+            }
+        }
+
+        let Reverse((end, _)) = match finishing.peek() {
+            Some(&next) => next,
+            None => break,
+        };
+        time = end;
+
+        while let Some(&Reverse((end, index))) = finishing.peek() {
+            if end != time {
+                break;
+            }
+            finishing.pop();
+
+            let (step, start) = running[index].take().expect("Finished worker was idle");
+            order.push(step);
+            tasks.push(Task { worker: index, step, start, end });
+
+            for &aft in dependents.get(step).into_iter().flatten() {
+                let n = waiting_on.get_mut(aft).expect("Dependent step is unknown");
+                *n -= 1;
+                if *n == 0 {
+                    available.insert(aft);
+                }
+            }
+        }
+    }
+
+    tasks.sort_by_key(|task| (task.start, task.worker));
+
+    Schedule { order, time, tasks, n_workers: workers.len() }
+}
+
+/// The longest chain of steps that each wait on the one before
+#[derive(Debug, Clone, PartialEq)]
+struct CriticalPath<'a> {
+    steps: Vec<&'a str>,
+    /// Seconds to finish the chain, which no number of workers can beat
+    time: u32,
+}
+
+/// Finds the critical path, preferring alphabetically earlier steps when
+/// chains take the same time.
+fn critical_path<'a>(graph: &Graph<'a>, duration: impl Fn(&str) -> u32) -> CriticalPath<'a> {
+    let mut finished_at = BTreeMap::new();
+    let mut came_from = BTreeMap::new();
+
+    for step in topological_order(graph) {
+        let latest = graph[step].iter().max_by_key(|&&bef| (finished_at[bef], Reverse(bef)));
+        let start = latest.map_or(0, |&bef| finished_at[bef]);
+        if let Some(&bef) = latest {
+            came_from.insert(step, bef);
+        }
+        finished_at.insert(step, start + duration(step).max(1));
+    }
+
+    let last = finished_at.iter().max_by_key(|&(&step, &time)| (time, Reverse(step)));
+    let (mut step, time) = match last {
+        Some((&step, &time)) => (step, time),
+        None => return CriticalPath { steps: Vec::new(), time: 0 },
+    };
+
+    let mut steps = vec![step];
+    while let Some(&bef) = came_from.get(step) {
+        steps.push(bef);
+        step = bef;
+    }
+    steps.reverse();
+
+    CriticalPath { steps, time }
+}
+
+/// The slow but obvious version of `schedule`, advancing one second at a
+/// time.
+#[cfg(test)]
+fn schedule_by_ticks<'a>(
+    graph: &Graph<'a>,
+    workers: &[Worker],
+    duration: impl Fn(&str) -> u32,
+) -> Schedule<'a> {
+    assert!(!workers.is_empty(), "Must have at least one worker");
 
     #[derive(Debug, Copy, Clone)]
-    struct WorkerState {
-        name: &'static str,
+    struct WorkerState<'a> {
+        name: &'a str,
         time_left: u32,
+        start: u32,
     }
-    let mut workers: Vec<Option<WorkerState>> = vec![None; N_WORKERS];
+
+    let mut graph = graph.clone();
+    let mut order = Vec::with_capacity(graph.len());
+    let mut tasks = Vec::with_capacity(graph.len());
+    let mut slots: Vec<Option<WorkerState>> = vec![None; workers.len()];
     let mut time = 0;
 
-    while !graph.is_empty() || workers.iter().any(|slot| slot.is_some()) {
-        for worker_slot in &mut workers {
+    loop {
+        let mut available: BTreeSet<_> = graph
+            .iter()
+            .filter_map(|(&aft, bef)| if bef.is_empty() { Some(aft) } else { None })
+            .collect();
+
+        for (worker, slot) in workers.iter().zip(&mut slots) {
+            if slot.is_some() {
+                continue;
+            }
+
+            if let Some(&name) = available.iter().find(|&&step| worker.can_execute(step)) {
+                available.remove(name);
+                *slot = Some(WorkerState { name, time_left: duration(name), start: time });
+                graph.remove(name);
+            }
+        }
+
+        if slots.iter().all(|slot| slot.is_none()) {
+            break;
+        }
+
+        time += 1;
+
+        for (index, worker_slot) in slots.iter_mut().enumerate() {
             if let Some(worker) = worker_slot {
-                worker.time_left -= 1;
+                worker.time_left = worker.time_left.saturating_sub(1);
 
                 if worker.time_left == 0 {
-                    for (_, bef) in &mut graph {
+                    for bef in graph.values_mut() {
                         bef.remove(worker.name);
                     }
 
                     order.push(worker.name);
+                    tasks.push(Task {
+                        worker: index,
+                        step: worker.name,
+                        start: worker.start,
+                        end: time,
+                    });
 
                     *worker_slot = None;
                 }
             }
         }
-
-        let available_slots = workers.iter_mut().filter(|slot| slot.is_none());
-
-        let available: BTreeSet<_> = graph.iter().filter_map(|(&aft, bef)| {
-            if bef.is_empty() { Some(aft) } else { None }
-        }).collect();
-
-        for (slot, name) in available_slots.zip(available) {
-            *slot = Some(WorkerState{ name, time_left: duration(name) } );
-            graph.remove(name);
-        }
-
-       time += 1;
     }
 
-    println!("The order is '{}'", order.iter().cloned().collect::<String>());
-    println!("It took {} ticks ({} seconds)", time, time - 1);
+    tasks.sort_by_key(|task| (task.start, task.worker));
 
-    Ok(())
+    Schedule { order, time, tasks, n_workers: workers.len() }
 }
 
-fn dependencies() -> Result<Vec<(&'static str, &'static str)>> {
+fn dependencies(input: &str) -> Result<Vec<(&str, &str)>> {
     let dep_regex = Regex::new(r"Step (\w+) must be finished before step (\w+) can begin.").unwrap();
 
-    INPUT.lines().map(|line| {
+    input.lines().map(|line| {
         let captures = dep_regex.captures(line).ok_or("Could not apply regex to line")?;
         let bef = captures.get(1).ok_or("Did not find before step")?;
         let aft = captures.get(2).ok_or("Did not find after step")?;
@@ -90,6 +708,434 @@ fn dependencies() -> Result<Vec<(&'static str, &'static str)>> {
     }).collect()
 }
 
-fn duration(s: &str) -> u32 {
-    (s.chars().next().unwrap() as u8 - b'A') as u32 + 1 + STEP_DURATION_BASE
+/// Writes single-letter steps run together, like the puzzle does, and
+/// longer step names separated by spaces.
+fn format_order(order: &[&str]) -> String {
+    if order.iter().all(|step| step.chars().count() == 1) {
+        order.concat()
+    } else {
+        order.join(" ")
+    }
+}
+
+/// How long each step takes, in seconds
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Durations {
+    /// Added to the position in the alphabet of single-letter steps
+    base: u32,
+    /// Steps with an explicit duration, overriding the alphabet rule
+    table: BTreeMap<String, u32>,
+}
+
+impl Durations {
+    fn new(base: u32) -> Self {
+        Durations { base, table: BTreeMap::new() }
+    }
+
+    /// Reads `STEP SECONDS` lines into the table, ignoring blank lines
+    /// and `#` comments.
+    fn parse_table(&mut self, input: &str) -> Result<()> {
+        for (i, line) in input.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let (step, seconds) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(step), Some(seconds), None) => (step, seconds),
+                _ => return Err(format!("Line {}: expected `STEP SECONDS`", i + 1).into()),
+            };
+
+            let seconds: u32 = seconds.parse().map_err(|e| format!("Line {}: {}", i + 1, e))?;
+            if seconds == 0 {
+                return Err(format!("Line {}: step {} must take some time", i + 1, step).into());
+            }
+
+            self.table.insert(step.to_string(), seconds);
+        }
+
+        Ok(())
+    }
+
+    fn get(&self, step: &str) -> Option<u32> {
+        if let Some(&seconds) = self.table.get(step) {
+            return Some(seconds);
+        }
+
+        match *step.as_bytes() {
+            [letter @ b'A'..=b'Z'] => Some(u32::from(letter - b'A') + 1 + self.base),
+            _ => None,
+        }
+    }
+}
+
+/// A worker that can only execute some of the steps
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Worker {
+    /// `None` when the worker can execute any step
+    steps: Option<BTreeSet<String>>,
+}
+
+impl Worker {
+    fn any() -> Self {
+        Worker { steps: None }
+    }
+
+    fn only<'a>(steps: impl IntoIterator<Item = &'a str>) -> Self {
+        Worker { steps: Some(steps.into_iter().map(String::from).collect()) }
+    }
+
+    fn can_execute(&self, step: &str) -> bool {
+        self.steps.as_ref().is_none_or(|steps| steps.contains(step))
+    }
+}
+
+/// Reads one worker per line, listing the steps it can execute or `*`
+/// for any step. Blank lines and `#` comments are ignored.
+fn parse_workers(input: &str) -> Result<Vec<Worker>> {
+    let workers: Vec<_> = input
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| if line == "*" { Worker::any() } else { Worker::only(line.split_whitespace()) })
+        .collect();
+
+    if workers.is_empty() {
+        return Err("Must have at least one worker".into());
+    }
+
+    Ok(workers)
+}
+
+/// Makes sure every step has a duration and a worker that can execute it
+fn check_schedule(graph: &Graph, workers: &[Worker], durations: &Durations) -> Result<()> {
+    if workers.is_empty() {
+        return Err("Must have at least one worker".into());
+    }
+
+    for &step in graph.keys() {
+        if durations.get(step).is_none() {
+            return Err(format!("No duration for step {}", step).into());
+        }
+        if !workers.iter().any(|worker| worker.can_execute(step)) {
+            return Err(format!("No worker can execute step {}", step).into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+static EXAMPLE: &str = "\
+Step C must be finished before step A can begin.
+Step C must be finished before step F can begin.
+Step A must be finished before step B can begin.
+Step A must be finished before step D can begin.
+Step B must be finished before step E can begin.
+Step D must be finished before step E can begin.
+Step F must be finished before step E can begin.
+";
+
+#[test]
+fn topological_order_example() {
+    let graph = graph(dependencies(EXAMPLE).unwrap());
+    assert_eq!(topological_order(&graph).concat(), "CABDFE");
+}
+
+#[test]
+fn schedule_example() {
+    let graph = graph(dependencies(EXAMPLE).unwrap());
+    let durations = Durations::new(0);
+    let schedule = schedule(&graph, &[Worker::any(), Worker::any()], |step| {
+        durations.get(step).unwrap()
+    });
+
+    assert_eq!(schedule.order.concat(), "CABFDE");
+    assert_eq!(schedule.time, 15);
+}
+
+#[cfg(test)]
+fn example_schedule() -> Schedule<'static> {
+    let graph = graph(dependencies(EXAMPLE).unwrap());
+    let durations = Durations::new(0);
+    schedule(&graph, &[Worker::any(), Worker::any()], |step| durations.get(step).unwrap())
+}
+
+#[test]
+fn schedule_records_tasks() {
+    let schedule = example_schedule();
+    let tasks: Vec<_> = schedule
+        .tasks
+        .iter()
+        .map(|task| (task.worker, task.step, task.start, task.end))
+        .collect();
+
+    assert_eq!(
+        tasks,
+        [
+            (0, "C", 0, 3),
+            (0, "A", 3, 4),
+            (1, "F", 3, 9),
+            (0, "B", 4, 6),
+            (0, "D", 6, 10),
+            (0, "E", 10, 15),
+        ]
+    );
+}
+
+#[test]
+fn schedule_table_example() {
+    let expected = "\
+Second   Worker 1   Worker 2   Done
+   0        C          .
+   1        C          .
+   2        C          .
+   3        A          F       C
+   4        B          F       CA
+   5        B          F       CA
+   6        D          F       CAB
+   7        D          F       CAB
+   8        D          F       CAB
+   9        D          .       CABF
+  10        E          .       CABFD
+  11        E          .       CABFD
+  12        E          .       CABFD
+  13        E          .       CABFD
+  14        E          .       CABFD
+  15        .          .       CABFDE
+";
+    assert_eq!(example_schedule().table(), expected);
+}
+
+#[test]
+fn schedule_gantt_example() {
+    let expected = "\
+Worker 1 |C--AB-D---E----|
+Worker 2 |   F-----      |
+          0         10
+";
+    assert_eq!(example_schedule().gantt(1), expected);
+
+    let expected = "\
+Worker 1 |CABD-E--|
+Worker 2 | F---   |
+          0
+";
+    assert_eq!(example_schedule().gantt(2), expected);
+}
+
+#[test]
+fn schedule_svg_has_a_bar_per_step() {
+    let svg = example_schedule().svg();
+    assert!(svg.starts_with("<svg "));
+    assert!(svg.ends_with("</svg>\n"));
+    assert_eq!(svg.matches("<rect ").count(), 6);
+    assert!(svg.contains("<title>F: 3s to 9s</title>"));
+}
+
+#[test]
+fn single_worker_follows_topological_order() {
+    let graph = graph(dependencies(INPUT).unwrap());
+    let durations = Durations::new(STEP_DURATION_BASE);
+    let schedule = schedule(&graph, &[Worker::any()], |step| durations.get(step).unwrap());
+
+    assert_eq!(schedule.order, topological_order(&graph));
+}
+
+#[test]
+fn puzzle_answers() {
+    let graph = graph(dependencies(INPUT).unwrap());
+    assert_eq!(topological_order(&graph).concat(), "CHILFNMORYKGAQXUVBZPSJWDET");
+
+    let durations = Durations::new(STEP_DURATION_BASE);
+    let workers = vec![Worker::any(); N_WORKERS];
+    let schedule = schedule(&graph, &workers, |step| durations.get(step).unwrap());
+    assert_eq!(schedule.order.concat(), "CINYHLFMRKOGQAXUZPVSBJWDET");
+    assert_eq!(schedule.time, 891);
+}
+
+#[test]
+fn schedule_matches_tick_by_tick_simulation() {
+    let example = graph(dependencies(EXAMPLE).unwrap());
+    let input = graph(dependencies(INPUT).unwrap());
+    let restricted = parse_workers("C A B\nD E F\n*\n").unwrap();
+
+    for graph in &[&example, &input] {
+        for &base in &[0, 1, STEP_DURATION_BASE] {
+            let durations = Durations::new(base);
+            let duration = |step: &str| durations.get(step).unwrap();
+
+            for n_workers in 1..=7 {
+                let workers = vec![Worker::any(); n_workers];
+                assert_eq!(
+                    schedule(graph, &workers, duration),
+                    schedule_by_ticks(graph, &workers, duration)
+                );
+            }
+            assert_eq!(
+                schedule(graph, &restricted, duration),
+                schedule_by_ticks(graph, &restricted, duration)
+            );
+        }
+    }
+}
+
+#[test]
+fn critical_path_example() {
+    let graph = graph(dependencies(EXAMPLE).unwrap());
+    let durations = Durations::new(0);
+    let duration = |step: &str| durations.get(step).unwrap();
+
+    let path = critical_path(&graph, duration);
+    assert_eq!(path, CriticalPath { steps: vec!["C", "F", "E"], time: 14 });
+
+    let unlimited = vec![Worker::any(); graph.len()];
+    assert_eq!(schedule(&graph, &unlimited, duration).time, path.time);
+    assert_eq!(critical_path(&Graph::new(), duration).time, 0);
+}
+
+#[test]
+fn critical_path_bounds_puzzle_schedule() {
+    let graph = graph(dependencies(INPUT).unwrap());
+    let durations = Durations::new(STEP_DURATION_BASE);
+    let duration = |step: &str| durations.get(step).unwrap();
+
+    let path = critical_path(&graph, duration);
+    assert_eq!(path.steps.iter().map(|&step| duration(step)).sum::<u32>(), path.time);
+    for pair in path.steps.windows(2) {
+        assert!(graph[pair[1]].contains(pair[0]));
+    }
+
+    let unlimited = vec![Worker::any(); graph.len()];
+    assert_eq!(schedule(&graph, &unlimited, duration).time, path.time);
+    assert!(path.time <= 891);
+}
+
+#[test]
+fn schedule_with_restricted_workers() {
+    let graph = graph(dependencies(EXAMPLE).unwrap());
+    let durations = Durations::new(0);
+    let workers = parse_workers("C A B\n# the other worker\nD E F\n").unwrap();
+    assert_eq!(workers, [Worker::only(vec!["A", "B", "C"]), Worker::only(vec!["D", "E", "F"])]);
+
+    check_schedule(&graph, &workers, &durations).unwrap();
+    let schedule = schedule(&graph, &workers, |step| durations.get(step).unwrap());
+
+    assert_eq!(schedule.order.concat(), "CABFDE");
+    assert_eq!(schedule.time, 18);
+}
+
+#[test]
+fn schedule_with_duration_table() {
+    let input = "\
+Step Shop must be finished before step Mix can begin.
+Step Preheat must be finished before step Bake can begin.
+Step Mix must be finished before step Bake can begin.
+Step Bake must be finished before step Serve can begin.
+";
+    let graph = graph(dependencies(input).unwrap());
+    let workers = [Worker::any(), Worker::any()];
+
+    let mut durations = Durations::new(0);
+    durations.parse_table("Shop 10\nMix 5\nPreheat 12 # oven is slow\n\nBake 20\n").unwrap();
+    assert_eq!(
+        check_schedule(&graph, &workers, &durations).unwrap_err().to_string(),
+        "No duration for step Serve"
+    );
+
+    durations.parse_table("Serve 1").unwrap();
+    check_schedule(&graph, &workers, &durations).unwrap();
+    let schedule = schedule(&graph, &workers, |step| durations.get(step).unwrap());
+
+    assert_eq!(format_order(&schedule.order), "Shop Preheat Mix Bake Serve");
+    assert_eq!(schedule.time, 36);
+}
+
+#[test]
+fn check_schedule_problems() {
+    let graph = graph(dependencies(EXAMPLE).unwrap());
+    let durations = Durations::new(0);
+
+    let workers = [Worker::only(vec!["A", "B", "C", "D", "F"])];
+    assert_eq!(
+        check_schedule(&graph, &workers, &durations).unwrap_err().to_string(),
+        "No worker can execute step E"
+    );
+    assert!(check_schedule(&graph, &[], &durations).is_err());
+    assert!(parse_workers("# nobody\n").is_err());
+
+    let mut durations = durations;
+    assert!(durations.parse_table("A").is_err());
+    assert!(durations.parse_table("A 0").is_err());
+    assert!(durations.parse_table("A 1 2").is_err());
+    assert!(durations.parse_table("A x").is_err());
+}
+
+#[test]
+fn diagnose_puzzle_input() {
+    assert_eq!(diagnose(&dependencies(INPUT).unwrap()), []);
+}
+
+#[test]
+fn diagnose_long_chain() {
+    let steps: Vec<_> = (0..20_000).map(|i| format!("S{}", i)).collect();
+    let mut dependencies: Vec<_> = steps
+        .windows(2)
+        .map(|w| (w[0].as_str(), w[1].as_str()))
+        .collect();
+    assert_eq!(diagnose(&dependencies), []);
+
+    // Closing the chain makes one long cycle
+    dependencies.push((&steps[19_999], &steps[0]));
+    match &diagnose(&dependencies)[..] {
+        [Problem::Cycle(cycle)] => assert_eq!(cycle.len(), 20_001),
+        problems => panic!("Expected one cycle, got {} problems", problems.len()),
+    }
+}
+
+#[test]
+fn diagnose_cycle() {
+    let mut dependencies = dependencies(EXAMPLE).unwrap();
+    dependencies.push(("E", "C"));
+
+    let problems = diagnose(&dependencies);
+    assert_eq!(problems, [Problem::Cycle(vec!["A", "B", "E", "C", "A"])]);
+    assert!(problems[0].is_fatal());
+    assert_eq!(
+        problems[0].to_string(),
+        "Error: steps depend on each other: A -> B -> E -> C -> A"
+    );
+}
+
+#[test]
+fn diagnose_separate_cycles() {
+    let dependencies = [("A", "B"), ("B", "A"), ("C", "D"), ("D", "E"), ("E", "C"), ("E", "F")];
+
+    assert_eq!(
+        diagnose(&dependencies),
+        [
+            Problem::Cycle(vec!["A", "B", "A"]),
+            Problem::Cycle(vec!["C", "D", "E", "C"]),
+        ]
+    );
+}
+
+#[test]
+fn diagnose_self_and_duplicate_dependencies() {
+    let mut dependencies = dependencies(EXAMPLE).unwrap();
+    dependencies.push(("B", "B"));
+    dependencies.push(("C", "A"));
+    dependencies.push(("C", "A"));
+
+    let problems = diagnose(&dependencies);
+    assert_eq!(
+        problems,
+        [
+            Problem::SelfDependency("B"),
+            Problem::DuplicateDependency { bef: "C", aft: "A", count: 3 },
+        ]
+    );
+    assert!(problems[0].is_fatal());
+    assert!(!problems[1].is_fatal());
 }
@@ -1,4 +1,4 @@
-use std::iter;
+use std::{fmt, fs, mem, ops::Range};
 
 static INPUT: &str = include_str!("../input.txt");
 
@@ -8,55 +8,928 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 fn main() -> Result<()> {
     let raw_data = raw_data()?;
 
-    let root = Node::new(raw_data)?;
+    let tree = Tree::new(&raw_data)?;
 
-    println!("Metadata sum is {}", root.metadata_sum());
-    println!("Root value is {}", root.value());
+    println!("Metadata sum is {}", tree.metadata_sum());
+    println!("Root value is {}", tree.value());
+
+    // Only the pretty printers need the allocating tree
+    let mut root = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tree" => print!("{}", lazy_node(&mut root, &raw_data)?.pretty()),
+            "--serialize" => {
+                let root = lazy_node(&mut root, &raw_data)?;
+                let numbers: Vec<_> = root.to_numbers().iter().map(ToString::to_string).collect();
+                println!("{}", numbers.join(" "));
+            }
+            "--dot" => {
+                let path = args.next().ok_or("Missing DOT path")?;
+                fs::write(path, lazy_node(&mut root, &raw_data)?.dot())?
+            }
+            "--stats" => println!("{}", tree.stats()),
+            "--above" => {
+                let threshold = args.next().ok_or("Missing value threshold")?.parse()?;
+                for (index, value) in tree.valued_above(threshold) {
+                    let path = format_path(&tree.path(index));
+                    println!("{} at {} has value {}", label(index), path, value);
+                }
+            }
+            "--references" => {
+                for (index, count) in tree.referenced_leaves() {
+                    let worth: usize = tree.metadata(index).iter().sum();
+                    println!(
+                        "{} at {} is counted {} times, worth {} each",
+                        label(index),
+                        format_path(&tree.path(index)),
+                        count,
+                        worth
+                    );
+                }
+            }
+            "--dead" => {
+                for index in tree.dead_children() {
+                    let path = format_path(&tree.path(index));
+                    println!("{} at {} is never referenced", label(index), path);
+                }
+            }
+            _ => return Err(format!("Unknown argument {}", arg).into()),
+        }
+    }
 
     Ok(())
 }
 
+/// Builds the node tree the first time it is needed
+fn lazy_node<'a>(node: &'a mut Option<Node>, data: &[usize]) -> Result<&'a Node> {
+    if node.is_none() {
+        *node = Some(Node::new(data.iter().cloned())?);
+    }
+    Ok(node.as_ref().expect("Node was just built"))
+}
+
+/// The tree can be arbitrarily deep, so nothing here recurses; building
+/// it, the queries, and even dropping the tree use explicit stacks or
+/// loops.
+#[derive(Debug)]
 struct Node {
     children: Vec<Node>,
     metadata: Vec<usize>,
 }
 
 impl Node {
-    fn new(data: impl IntoIterator<Item = usize>) -> Result<Self> {
-        fn new_inner(data: &mut impl Iterator<Item = usize>) -> Result<Node> {
-            let n_children = data.next().ok_or("Missing data count")?;
-            let n_metadata = data.next().ok_or("Missing metadata count")?;
+    /// Parses with `Tree::new`, then copies every node out of it
+    fn new(data: impl IntoIterator<Item = usize>) -> Result<Self, ParseError> {
+        let data: Vec<_> = data.into_iter().collect();
+        let tree = Tree::new(&data)?;
 
-            let children = iter::repeat_with(|| new_inner(data)).take(n_children).collect::<Result<_>>()?;
-            let metadata = data.take(n_metadata).collect();
+        // Children come after their parents, so building from the back
+        // means every child is ready before its parent needs it
+        let mut built: Vec<Option<Node>> = Vec::with_capacity(tree.nodes.len());
+        built.resize_with(tree.nodes.len(), || None);
 
-            Ok(Node { children, metadata })
+        for index in (0..tree.nodes.len()).rev() {
+            let children = tree
+                .children(index)
+                .iter()
+                .map(|&child| built[child].take().expect("Child was not built"))
+                .collect();
+            let metadata = tree.metadata(index).to_vec();
+            built[index] = Some(Node { children, metadata });
         }
 
-        new_inner(&mut data.into_iter())
+        Ok(built[0].take().expect("Tree has no root"))
     }
 
+    /// Kept to check `Tree` against
+    #[cfg(test)]
     fn metadata_sum(&self) -> usize {
-        let children = self.children.iter().map(|c| c.metadata_sum()).sum::<usize>();
-        let direct = self.metadata.iter().sum::<usize>();
+        let mut sum = 0;
+        let mut stack = vec![self];
 
-        children + direct
+        while let Some(node) = stack.pop() {
+            sum += node.metadata.iter().sum::<usize>();
+            stack.extend(&node.children);
+        }
+
+        sum
     }
 
+    #[cfg(test)]
     fn value(&self) -> usize {
-        if self.children.is_empty() {
+        // Visit each node twice: first to queue its children, then, once
+        // all of their values are known, to compute its own.
+        let mut values = Vec::new();
+        let mut stack = vec![(self, false)];
+
+        while let Some((node, children_done)) = stack.pop() {
+            if children_done {
+                let child_values = values.split_off(values.len() - node.children.len());
+                values.push(node.value_from(&child_values));
+            } else {
+                stack.push((node, true));
+                stack.extend(node.children.iter().rev().map(|child| (child, false)));
+            }
+        }
+
+        values.pop().expect("The root has a value")
+    }
+
+    /// Every node with its depth, parents before children
+    fn preorder(&self) -> Vec<(usize, &Node)> {
+        let mut nodes = Vec::new();
+        let mut stack = vec![(0, self)];
+
+        while let Some((depth, node)) = stack.pop() {
+            nodes.push((depth, node));
+            stack.extend(node.children.iter().rev().map(|child| (depth + 1, child)));
+        }
+
+        nodes
+    }
+
+    /// The value of every node, in the same order as `preorder`
+    fn values(&self) -> Vec<usize> {
+        let nodes = self.preorder();
+        let mut values = vec![0; nodes.len()];
+
+        // Walking backwards finishes each subtree at its root, leaving the
+        // values of a node's children on the stack first child on top.
+        let mut stack = Vec::new();
+        for (index, &(_, node)) in nodes.iter().enumerate().rev() {
+            let child_values: Vec<_> = (0..node.children.len())
+                .map(|_| stack.pop().expect("Child value is missing"))
+                .collect();
+            values[index] = node.value_from(&child_values);
+            stack.push(values[index]);
+        }
+
+        values
+    }
+
+    /// The flat header, children, metadata encoding that `new` parses
+    fn to_numbers(&self) -> Vec<usize> {
+        enum Visit<'a> {
+            Header(&'a Node),
+            Metadata(&'a Node),
+        }
+
+        let mut numbers = Vec::new();
+        let mut stack = vec![Visit::Header(self)];
+
+        while let Some(visit) = stack.pop() {
+            match visit {
+                Visit::Header(node) => {
+                    numbers.extend(&[node.children.len(), node.metadata.len()]);
+                    stack.push(Visit::Metadata(node));
+                    stack.extend(node.children.iter().rev().map(Visit::Header));
+                }
+                Visit::Metadata(node) => numbers.extend(&node.metadata),
+            }
+        }
+
+        numbers
+    }
+
+    /// One line per node with its label, metadata, and value, drawn as an
+    /// indented tree
+    fn pretty(&self) -> String {
+        let nodes = self.preorder();
+        let values = self.values();
+        let mut out = String::new();
+
+        // Whether each ancestor of the current node was the last child
+        let mut last_children: Vec<bool> = Vec::new();
+        let mut remaining_siblings: Vec<usize> = Vec::new();
+
+        for (index, &(depth, node)) in nodes.iter().enumerate() {
+            last_children.truncate(depth);
+            remaining_siblings.truncate(depth);
+
+            if depth > 0 {
+                let remaining = &mut remaining_siblings[depth - 1];
+                *remaining -= 1;
+                let is_last = *remaining == 0;
+
+                for &ancestor_last in &last_children[1..] {
+                    out.push_str(if ancestor_last { "    " } else { "│   " });
+                }
+                out.push_str(if is_last { "└── " } else { "├── " });
+                last_children.push(is_last);
+            } else {
+                last_children.push(true);
+            }
+            remaining_siblings.push(node.children.len());
+
+            out.push_str(&format!(
+                "{} {:?} value {}\n",
+                label(index),
+                node.metadata,
+                values[index]
+            ));
+        }
+
+        out
+    }
+
+    /// A Graphviz digraph of the tree; metadata referring to a child is
+    /// drawn as a dashed edge labelled with the number of references.
+    fn dot(&self) -> String {
+        let nodes = self.preorder();
+        let values = self.values();
+        let mut out = String::from("digraph license {\n    node [shape=box];\n");
+
+        // A node's children follow it in preorder, each one after all
+        // of the previous child's descendants
+        let mut sizes = vec![1; nodes.len()];
+        for index in (0..nodes.len()).rev() {
+            let (_, node) = nodes[index];
+            let mut child_index = index + 1;
+            for _ in &node.children {
+                sizes[index] += sizes[child_index];
+                child_index += sizes[child_index];
+            }
+        }
+
+        for (index, &(_, node)) in nodes.iter().enumerate() {
+            let metadata: Vec<_> = node.metadata.iter().map(ToString::to_string).collect();
+            out.push_str(&format!(
+                "    {} [label=\"{}\\n[{}]\\nvalue {}\"];\n",
+                label(index),
+                label(index),
+                metadata.join(" "),
+                values[index]
+            ));
+
+            let mut child_labels = Vec::new();
+            let mut child_index = index + 1;
+            for _ in &node.children {
+                child_labels.push(label(child_index));
+                out.push_str(&format!("    {} -> {};\n", label(index), label(child_index)));
+                child_index += sizes[child_index];
+            }
+
+            let mut references = vec![0; node.children.len()];
+            for &idx in &node.metadata {
+                if let Some(count) = idx.checked_sub(1).and_then(|i| references.get_mut(i)) {
+                    *count += 1;
+                }
+            }
+            for (child_label, &count) in child_labels.iter().zip(&references) {
+                if count > 0 {
+                    out.push_str(&format!(
+                        "    {} -> {} [style=dashed, label=\"×{}\"];\n",
+                        label(index),
+                        child_label,
+                        count
+                    ));
+                }
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+
+    /// The value of this node, given the values of its children
+    fn value_from(&self, child_values: &[usize]) -> usize {
+        if child_values.is_empty() {
             self.metadata.iter().sum::<usize>()
         } else {
             self.metadata.iter().flat_map(|idx| {
                 match idx {
                     0 => None,
-                    idx => self.children.get(idx - 1).map(|n| n.value()),
+                    idx => child_values.get(idx - 1),
                 }
             }).sum::<usize>()
         }
     }
 }
 
+impl Drop for Node {
+    fn drop(&mut self) {
+        let mut descendants = mem::take(&mut self.children);
+        while let Some(mut node) = descendants.pop() {
+            descendants.append(&mut node.children);
+        }
+    }
+}
+
+/// The same tree as `Node`, but stored in a few flat buffers instead of
+/// allocating for every node. Metadata is borrowed straight from the
+/// numbers the tree was parsed from.
+struct Tree<'a> {
+    /// Parents before children, with the root first
+    nodes: Vec<FlatNode>,
+    /// Each node's children, as indices into `nodes`
+    children: Vec<usize>,
+    /// The encoded tree; metadata is read from here
+    data: &'a [usize],
+}
+
+struct FlatNode {
+    parent: Option<usize>,
+    /// Range into `Tree::children`
+    children: Range<usize>,
+    /// Range into `Tree::data`
+    metadata: Range<usize>,
+}
+
+impl<'a> Tree<'a> {
+    /// The one parser for the license format; `Node::new` is built on it
+    fn new(data: &'a [usize]) -> Result<Self, ParseError> {
+        /// A node whose header has been read but whose children are
+        /// still being parsed
+        struct Partial {
+            index: usize,
+            offset: usize,
+            n_children: usize,
+            n_metadata: usize,
+            /// Where this node's finished children start in `pending`
+            first_pending: usize,
+        }
+
+        let mut nodes = Vec::new();
+        let mut children = Vec::new();
+        let mut pending = Vec::new();
+        let mut parents: Vec<Partial> = Vec::new();
+        let mut offset = 0;
+
+        // Each parent's finished children run up to where the next one's
+        // start, and the last parent's up to `end`
+        let error = |parents: &[Partial], end: usize, offset, kind| {
+            let ends = parents.iter().skip(1).map(|p| p.first_pending).chain(Some(end));
+            let path = parents.iter().zip(ends).map(|(p, end)| end - p.first_pending).collect();
+            ParseError { offset, path, kind }
+        };
+
+        loop {
+            let start = offset;
+            let n_children = *data.get(offset).ok_or_else(|| {
+                error(&parents, pending.len(), start, ParseErrorKind::MissingChildCount)
+            })?;
+            let n_metadata = *data.get(offset + 1).ok_or_else(|| {
+                error(&parents, pending.len(), start, ParseErrorKind::MissingMetadataCount)
+            })?;
+            offset += 2;
+
+            let mut current = Partial {
+                index: nodes.len(),
+                offset: start,
+                n_children,
+                n_metadata,
+                first_pending: pending.len(),
+            };
+            let parent = parents.last().map(|parent| parent.index);
+            nodes.push(FlatNode { parent, children: 0..0, metadata: 0..0 });
+
+            // Finish every node that has all of its children, walking
+            // back up until one still needs another child
+            while pending.len() - current.first_pending == current.n_children {
+                let found = data.len() - offset;
+                if found < current.n_metadata {
+                    let kind =
+                        ParseErrorKind::MissingMetadata { expected: current.n_metadata, found };
+                    return Err(error(&parents, current.first_pending, current.offset, kind));
+                }
+
+                let metadata = offset..offset + current.n_metadata;
+                offset = metadata.end;
+
+                let first_child = children.len();
+                children.extend(pending.drain(current.first_pending..));
+                let node = &mut nodes[current.index];
+                node.children = first_child..children.len();
+                node.metadata = metadata;
+
+                match parents.pop() {
+                    Some(parent) => {
+                        pending.push(current.index);
+                        current = parent;
+                    }
+                    None => {
+                        if offset != data.len() {
+                            let kind = ParseErrorKind::TrailingData { count: data.len() - offset };
+                            return Err(error(&[], 0, offset, kind));
+                        }
+                        return Ok(Tree { nodes, children, data });
+                    }
+                }
+            }
+
+            parents.push(current);
+        }
+    }
+
+    fn children(&self, index: usize) -> &[usize] {
+        &self.children[self.nodes[index].children.clone()]
+    }
+
+    fn metadata(&self, index: usize) -> &'a [usize] {
+        &self.data[self.nodes[index].metadata.clone()]
+    }
+
+    fn metadata_sum(&self) -> usize {
+        (0..self.nodes.len()).map(|index| self.metadata(index).iter().sum::<usize>()).sum()
+    }
+
+    /// The child of `index` that a metadata entry refers to, if any
+    fn referenced_child(&self, index: usize, entry: usize) -> Option<usize> {
+        entry.checked_sub(1).and_then(|i| self.children(index).get(i)).cloned()
+    }
+
+    fn value(&self) -> usize {
+        self.value_of(0)
+    }
+
+    /// The value of every node, indexed like `nodes`
+    fn values(&self) -> Vec<usize> {
+        let mut values = vec![0; self.nodes.len()];
+
+        // Children always come after their parent
+        for index in (0..self.nodes.len()).rev() {
+            let metadata = self.metadata(index);
+            values[index] = if self.children(index).is_empty() {
+                metadata.iter().sum()
+            } else {
+                metadata
+                    .iter()
+                    .flat_map(|&entry| self.referenced_child(index, entry))
+                    .map(|child| values[child])
+                    .sum()
+            };
+        }
+
+        values
+    }
+
+    /// The child indices leading from the root to a node
+    fn path(&self, mut index: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        while let Some(parent) = self.nodes[index].parent {
+            let position = self.children(parent).iter().position(|&child| child == index);
+            path.push(position.expect("Node is one of its parent's children"));
+            index = parent;
+        }
+        path.reverse();
+        path
+    }
+
+    fn stats(&self) -> Stats {
+        let mut depths = vec![0; self.nodes.len()];
+        for (index, node) in self.nodes.iter().enumerate().skip(1) {
+            depths[index] = depths[node.parent.expect("Only the root has no parent")] + 1;
+        }
+
+        let levels = depths.iter().max().map_or(0, |&deepest| deepest + 1);
+        let mut breadths = vec![0; levels];
+        for &depth in &depths {
+            breadths[depth] += 1;
+        }
+
+        Stats {
+            nodes: self.nodes.len(),
+            leaves: self.nodes.iter().filter(|node| node.children.is_empty()).count(),
+            levels,
+            widest_level: breadths.iter().cloned().max().unwrap_or(0),
+            most_children: self.nodes.iter().map(|node| node.children.len()).max().unwrap_or(0),
+        }
+    }
+
+    /// Nodes worth more than `threshold`, along with their value
+    fn valued_above(&self, threshold: usize) -> Vec<(usize, usize)> {
+        self.values().into_iter().enumerate().filter(|&(_, value)| value > threshold).collect()
+    }
+
+    /// How many times each node is counted towards the root value by
+    /// following metadata references down from the root. Zero means the
+    /// node is dead.
+    fn reference_counts(&self) -> Vec<usize> {
+        let mut counts = vec![0_usize; self.nodes.len()];
+        counts[0] = 1;
+
+        // Parents always come before their children
+        for index in 0..self.nodes.len() {
+            if counts[index] == 0 {
+                continue;
+            }
+            for &entry in self.metadata(index) {
+                if let Some(child) = self.referenced_child(index, entry) {
+                    counts[child] = counts[child].saturating_add(counts[index]);
+                }
+            }
+        }
+
+        counts
+    }
+
+    /// Leaves that contribute to the root value, and how many times each
+    fn referenced_leaves(&self) -> Vec<(usize, usize)> {
+        self.reference_counts()
+            .into_iter()
+            .enumerate()
+            .filter(|&(index, count)| count > 0 && self.children(index).is_empty())
+            .collect()
+    }
+
+    /// The topmost nodes that never contribute to the root value; none of
+    /// their descendants do either.
+    fn dead_children(&self) -> Vec<usize> {
+        let counts = self.reference_counts();
+        (1..self.nodes.len())
+            .filter(|&index| {
+                let parent = self.nodes[index].parent.expect("Only the root has no parent");
+                counts[index] == 0 && counts[parent] > 0
+            })
+            .collect()
+    }
+
+    /// Only evaluates the nodes that metadata actually refers to, and
+    /// each of those only once no matter how often it is referred to.
+    fn value_of(&self, index: usize) -> usize {
+        let mut memo = vec![None; self.nodes.len()];
+        let mut stack = vec![index];
+
+        while let Some(&current) = stack.last() {
+            if memo[current].is_some() {
+                stack.pop();
+                continue;
+            }
+
+            let metadata = self.metadata(current);
+            if self.children(current).is_empty() {
+                memo[current] = Some(metadata.iter().sum::<usize>());
+                stack.pop();
+                continue;
+            }
+
+            let referenced =
+                metadata.iter().flat_map(|&entry| self.referenced_child(current, entry));
+            let unknown: Vec<_> =
+                referenced.clone().filter(|&child| memo[child].is_none()).collect();
+
+            if unknown.is_empty() {
+                memo[current] = Some(referenced.map(|child| memo[child].unwrap_or(0)).sum());
+                stack.pop();
+            } else {
+                stack.extend(unknown);
+            }
+        }
+
+        memo[index].expect("Value was just computed")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Stats {
+    nodes: usize,
+    leaves: usize,
+    /// How deep the tree is, counting the root as one level
+    levels: usize,
+    /// The most nodes at the same depth
+    widest_level: usize,
+    most_children: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} nodes ({} leaves) in {} levels, at most {} on one level \
+             and {} children of one node",
+            self.nodes, self.leaves, self.levels, self.widest_level, self.most_children
+        )
+    }
+}
+
+/// Names nodes like the puzzle does, continuing after `Z` with `AA`,
+/// `AB`, and so on.
+fn label(mut index: usize) -> String {
+    let mut label = Vec::new();
+    loop {
+        label.push(b'A' + (index % 26) as u8);
+        index /= 26;
+        if index == 0 {
+            break;
+        }
+        index -= 1;
+    }
+    label.reverse();
+    String::from_utf8(label).expect("Labels are ASCII")
+}
+
+/// Child indices from the root, like `root/1/0`
+fn format_path(path: &[usize]) -> String {
+    let mut formatted = String::from("root");
+    for index in path {
+        formatted.push_str(&format!("/{}", index));
+    }
+    formatted
+}
+
+/// A license tree that doesn't follow the encoding
+#[derive(Debug, Clone, PartialEq)]
+struct ParseError {
+    /// Position in the number stream of the malformed node's header, or
+    /// of the first number after the root
+    offset: usize,
+    /// Child indices leading from the root to the malformed node
+    path: Vec<usize>,
+    kind: ParseErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ParseErrorKind {
+    MissingChildCount,
+    MissingMetadataCount,
+    MissingMetadata { expected: usize, found: usize },
+    TrailingData { count: usize },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::MissingChildCount => write!(f, "Missing child count")?,
+            ParseErrorKind::MissingMetadataCount => write!(f, "Missing metadata count")?,
+            ParseErrorKind::MissingMetadata { expected, found } => {
+                write!(f, "Expected {} metadata entries but found {}", expected, found)?
+            }
+            ParseErrorKind::TrailingData { count } => {
+                write!(f, "Found {} numbers after the root node", count)?
+            }
+        }
+
+        write!(f, " at offset {} (node {})", self.offset, format_path(&self.path))
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 fn raw_data() -> Result<Vec<usize>> {
     INPUT.split_whitespace().map(|n| n.parse().map_err(Into::into)).collect()
 }
+
+#[cfg(test)]
+static EXAMPLE: &[usize] = &[2, 3, 0, 3, 10, 11, 12, 1, 1, 0, 1, 99, 2, 1, 1, 2];
+
+/// A chain of nodes `depth` deep, each with one child and one metadata
+/// entry pointing at it, ending in a leaf worth `leaf`
+#[cfg(test)]
+fn chain(depth: usize, leaf: usize) -> Vec<usize> {
+    let mut data = Vec::with_capacity(depth * 3 + 3);
+    for _ in 0..depth {
+        data.extend(&[1, 1]);
+    }
+    data.extend(&[0, 1, leaf]);
+    data.extend(std::iter::repeat_n(1, depth));
+    data
+}
+
+#[test]
+fn example() {
+    let root = Node::new(EXAMPLE.iter().cloned()).unwrap();
+
+    assert_eq!(root.children.len(), 2);
+    assert_eq!(root.metadata, [1, 1, 2]);
+    assert_eq!(root.children[0].metadata, [10, 11, 12]);
+    assert_eq!(root.children[1].children[0].metadata, [99]);
+
+    assert_eq!(root.metadata_sum(), 138);
+    assert_eq!(root.value(), 66);
+}
+
+#[test]
+fn puzzle_answers() {
+    let root = Node::new(raw_data().unwrap()).unwrap();
+
+    assert_eq!(root.metadata_sum(), 47647);
+    assert_eq!(root.value(), 23636);
+}
+
+#[test]
+fn deep_chain_does_not_overflow_the_stack() {
+    let depth = 100_000;
+    let root = Node::new(chain(depth, 7)).unwrap();
+
+    assert_eq!(root.metadata_sum(), 7 + depth);
+    assert_eq!(root.value(), 7);
+}
+
+#[test]
+fn truncated_metadata() {
+    let error = Node::new(EXAMPLE[..13].iter().cloned()).unwrap_err();
+    assert_eq!(
+        error,
+        ParseError {
+            offset: 0,
+            path: vec![],
+            kind: ParseErrorKind::MissingMetadata { expected: 3, found: 0 },
+        }
+    );
+
+    let error = Node::new(EXAMPLE[..11].iter().cloned()).unwrap_err();
+    assert_eq!(error.offset, 9);
+    assert_eq!(error.path, [1, 0]);
+    assert_eq!(error.kind, ParseErrorKind::MissingMetadata { expected: 1, found: 0 });
+    assert_eq!(
+        error.to_string(),
+        "Expected 1 metadata entries but found 0 at offset 9 (node root/1/0)"
+    );
+}
+
+#[test]
+fn truncated_header() {
+    let error = Node::new(EXAMPLE[..8].iter().cloned()).unwrap_err();
+    assert_eq!(error.offset, 7);
+    assert_eq!(error.path, [1]);
+    assert_eq!(error.kind, ParseErrorKind::MissingMetadataCount);
+
+    let error = Node::new(EXAMPLE[..7].iter().cloned()).unwrap_err();
+    assert_eq!(error.offset, 7);
+    assert_eq!(error.path, [1]);
+    assert_eq!(error.kind, ParseErrorKind::MissingChildCount);
+
+    let error = Node::new(vec![]).unwrap_err();
+    assert_eq!(error.to_string(), "Missing child count at offset 0 (node root)");
+}
+
+#[test]
+fn trailing_data() {
+    let error = Node::new(EXAMPLE.iter().cloned().chain(vec![4, 5])).unwrap_err();
+    assert_eq!(
+        error,
+        ParseError { offset: 16, path: vec![], kind: ParseErrorKind::TrailingData { count: 2 } }
+    );
+    assert_eq!(error.to_string(), "Found 2 numbers after the root node at offset 16 (node root)");
+}
+
+#[test]
+fn round_trip() {
+    let root = Node::new(EXAMPLE.iter().cloned()).unwrap();
+    assert_eq!(root.to_numbers(), EXAMPLE);
+
+    let data = raw_data().unwrap();
+    assert_eq!(Node::new(data.iter().cloned()).unwrap().to_numbers(), data);
+
+    let data = chain(100_000, 7);
+    assert_eq!(Node::new(data.iter().cloned()).unwrap().to_numbers(), data);
+}
+
+#[test]
+fn pretty_example() {
+    let root = Node::new(EXAMPLE.iter().cloned()).unwrap();
+    let expected = "\
+A [1, 1, 2] value 66
+├── B [10, 11, 12] value 33
+└── C [2] value 0
+    └── D [99] value 99
+";
+    assert_eq!(root.pretty(), expected);
+    assert_eq!(root.values(), [66, 33, 0, 99]);
+}
+
+#[test]
+fn pretty_nested_siblings() {
+    // A has children B and E; B has children C and D
+    let root = Node::new(vec![2, 0, 2, 0, 0, 1, 1, 0, 1, 2, 0, 1, 3]).unwrap();
+    let expected = "\
+A [] value 0
+├── B [] value 0
+│   ├── C [1] value 1
+│   └── D [2] value 2
+└── E [3] value 3
+";
+    assert_eq!(root.pretty(), expected);
+}
+
+#[test]
+fn dot_example() {
+    let root = Node::new(EXAMPLE.iter().cloned()).unwrap();
+    let expected = r#"digraph license {
+    node [shape=box];
+    A [label="A\n[1 1 2]\nvalue 66"];
+    A -> B;
+    A -> C;
+    A -> B [style=dashed, label="×2"];
+    A -> C [style=dashed, label="×1"];
+    B [label="B\n[10 11 12]\nvalue 33"];
+    C [label="C\n[2]\nvalue 0"];
+    C -> D;
+    D [label="D\n[99]\nvalue 99"];
+}
+"#;
+    assert_eq!(root.dot(), expected);
+}
+
+#[test]
+fn labels() {
+    let labels: Vec<_> = [0, 1, 25, 26, 27, 51, 52, 701, 702].iter().map(|&i| label(i)).collect();
+    assert_eq!(labels, ["A", "B", "Z", "AA", "AB", "AZ", "BA", "ZZ", "AAA"]);
+}
+
+#[test]
+fn tree_matches_node() {
+    let root = Node::new(EXAMPLE.iter().cloned()).unwrap();
+    let tree = Tree::new(EXAMPLE).unwrap();
+    assert_eq!(tree.children(0), [1, 2]);
+    assert_eq!(tree.children(2), [3]);
+    assert_eq!(tree.metadata(3), [99]);
+    assert_eq!((tree.metadata_sum(), tree.value()), (root.metadata_sum(), root.value()));
+    assert_eq!((0..4).map(|index| tree.value_of(index)).collect::<Vec<_>>(), root.values());
+
+    let data = raw_data().unwrap();
+    let tree = Tree::new(&data).unwrap();
+    assert_eq!((tree.metadata_sum(), tree.value()), (47647, 23636));
+
+    let data = chain(100_000, 7);
+    let tree = Tree::new(&data).unwrap();
+    assert_eq!((tree.metadata_sum(), tree.value()), (7 + 100_000, 7));
+}
+
+#[test]
+fn tree_reports_the_same_errors_as_node() {
+    let mut inputs: Vec<Vec<usize>> = (0..EXAMPLE.len()).map(|n| EXAMPLE[..n].to_vec()).collect();
+    inputs.push(EXAMPLE.iter().cloned().chain(vec![4, 5]).collect());
+    inputs.push(vec![0, 2, 1]);
+
+    for data in inputs {
+        assert_eq!(
+            Tree::new(&data).err(),
+            Node::new(data.iter().cloned()).err(),
+            "Parsing {:?}",
+            data
+        );
+    }
+}
+
+#[test]
+fn tree_value_evaluates_shared_children_once() {
+    // Every node refers to its only child three times, so evaluating
+    // each reference separately would take 3^depth steps
+    let depth = 10_000;
+    let mut data = Vec::new();
+    for _ in 0..depth {
+        data.extend(&[1, 3]);
+    }
+    data.extend(&[0, 1, 0]);
+    data.extend(std::iter::repeat_n(1, depth * 3));
+
+    assert_eq!(Tree::new(&data).unwrap().value(), 0);
+
+    // Three levels of the same, ending with a leaf worth 2
+    let data = [1, 3, 1, 3, 1, 3, 0, 1, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1];
+    assert_eq!(Tree::new(&data).unwrap().value(), 2 * 27);
+}
+
+#[test]
+fn queries_example() {
+    let tree = Tree::new(EXAMPLE).unwrap();
+
+    assert_eq!(
+        tree.stats(),
+        Stats { nodes: 4, leaves: 2, levels: 3, widest_level: 2, most_children: 2 }
+    );
+    assert_eq!(tree.values(), [66, 33, 0, 99]);
+    assert_eq!(tree.valued_above(50), [(0, 66), (3, 99)]);
+    assert_eq!(tree.path(3), [1, 0]);
+    assert_eq!(format_path(&tree.path(3)), "root/1/0");
+
+    assert_eq!(tree.reference_counts(), [1, 2, 1, 0]);
+    assert_eq!(tree.referenced_leaves(), [(1, 2)]);
+    assert_eq!(tree.dead_children(), [3]);
+}
+
+#[test]
+fn queries_puzzle_input() {
+    let data = raw_data().unwrap();
+    let tree = Tree::new(&data).unwrap();
+
+    let values = tree.values();
+    assert_eq!(values[0], tree.value());
+    for (index, &value) in values.iter().enumerate().step_by(97) {
+        assert_eq!(value, tree.value_of(index));
+    }
+
+    // The root value is made up entirely of the referenced leaves
+    let from_leaves: usize = tree
+        .referenced_leaves()
+        .into_iter()
+        .map(|(index, count)| count * tree.metadata(index).iter().sum::<usize>())
+        .sum();
+    assert_eq!(from_leaves, tree.value());
+
+    let stats = tree.stats();
+    assert_eq!(stats.nodes, Node::new(data.iter().cloned()).unwrap().preorder().len());
+
+    // Dead children are the topmost nodes that aren't referenced
+    let counts = tree.reference_counts();
+    let dead = tree.dead_children();
+    assert!(!dead.is_empty());
+    for index in dead {
+        let parent = tree.nodes[index].parent.unwrap();
+        assert_eq!(counts[index], 0);
+        assert!(counts[parent] > 0);
+    }
+}
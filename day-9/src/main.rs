@@ -1,5 +1,6 @@
 use regex::Regex;
 use std::collections::VecDeque;
+use std::fmt::Write;
 
 static INPUT: &str = include_str!("../input.txt");
 
@@ -8,63 +9,212 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 
 fn main() -> Result<()> {
     let mut config = config()?;
+    let mut replay = false;
+    let mut show_scores = false;
+    let mut show_progress = false;
+    let mut rules = Rules::default();
 
-    println!("{}", config.run_game());
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--players" => config.players = args.next().ok_or("Missing players")?.parse()?,
+            "--points" => config.points = args.next().ok_or("Missing points")?.parse()?,
+            "--replay" => replay = true,
+            "--scores" => show_scores = true,
+            "--progress" => show_progress = true,
+            "--modulus" => {
+                rules.scoring_modulus = args.next().ok_or("Missing scoring modulus")?.parse()?
+            }
+            "--removal" => {
+                rules.removal_distance = args.next().ok_or("Missing removal distance")?.parse()?
+            }
+            "--offset" => {
+                rules.insertion_offset = args.next().ok_or("Missing insertion offset")?.parse()?
+            }
+            _ => return Err(format!("Unknown argument {}", arg).into()),
+        }
+    }
+
+    if config.players == 0 {
+        return Err("Must have at least one player".into());
+    }
+    if rules.scoring_modulus == 0 {
+        return Err("The scoring modulus must be at least 1".into());
+    }
+
+    let progress = |percent| {
+        if show_progress {
+            eprintln!("{}%...", percent);
+        }
+    };
+
+    // Each line of the replay shows the whole board, so only replay the
+    // first game
+    let game = config.play(&rules, replay, progress);
+    if let Some(replay) = &game.replay {
+        print!("{}", replay);
+    }
+    report(&game, show_scores);
 
     config.points *= 100;
-    println!("{}", config.run_game());
+    report(&config.play(&rules, false, progress), show_scores);
 
     Ok(())
 }
 
+fn report(game: &Game, show_scores: bool) {
+    if show_scores {
+        for (player, score) in game.scores.iter().enumerate() {
+            println!("Player {:3}: {}", player + 1, score);
+        }
+    }
+
+    let (winner, high_score) = game.winner();
+    println!("Player {} wins with {} points", winner + 1, high_score);
+}
+
+/// The parts of the game that can be varied
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Rules {
+    /// Marbles that are a multiple of this are scored instead of placed
+    scoring_modulus: usize,
+    /// How far counter-clockwise of the current marble to remove a marble
+    /// from when scoring
+    removal_distance: usize,
+    /// How far clockwise of the current marble to place the new marble
+    /// after
+    insertion_offset: usize,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules { scoring_modulus: 23, removal_distance: 7, insertion_offset: 1 }
+    }
+}
+
 #[derive(Debug, Copy, Clone)]
 struct Config {
     players: usize,
     points: usize,
 }
 
+/// Everything that happened in a game
+#[derive(Debug, Clone, PartialEq)]
+struct Game {
+    /// The final score of each player
+    scores: Vec<usize>,
+    /// Every marble placement that scored, in order
+    scoring: Vec<Scoring>,
+    /// The board before each turn, if it was asked for
+    replay: Option<String>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Scoring {
+    player: usize,
+    /// The marble that was placed
+    marble: usize,
+    /// The marble that was removed from the board
+    removed: usize,
+}
+
+impl Game {
+    /// The player with the highest score, preferring the first player to
+    /// reach it when tied
+    fn winner(&self) -> (usize, usize) {
+        self.scores
+            .iter()
+            .cloned()
+            .enumerate()
+            .max_by_key(|&(player, score)| (score, std::cmp::Reverse(player)))
+            .expect("No players")
+    }
+}
+
 impl Config {
-    fn run_game(&self) -> usize {
+    /// Plays quietly with the usual rules, without a replay
+    #[cfg(test)]
+    fn run_game(&self) -> Game {
+        self.play(&Rules::default(), false, |_| {})
+    }
+
+    /// The board is kept rotated so the current marble is always at the
+    /// back, with clockwise running from the front. Every move only
+    /// rotates by a few places, so each one takes constant time.
+    ///
+    /// The replay shows each turn's player, the index of the current
+    /// marble, and the board starting from marble 0.
+    ///
+    /// `on_progress` is told the percentage of marbles placed every time
+    /// another 5% have been.
+    ///
+    /// Should scoring ever remove the last marble, the next marble that
+    /// would score is placed instead, as there is nothing to remove.
+    fn play(&self, rules: &Rules, replay: bool, mut on_progress: impl FnMut(usize)) -> Game {
+        assert!(rules.scoring_modulus > 0, "The scoring modulus must be at least 1");
+
         let mut scores = vec![0; self.players];
-        let mut board: VecDeque<_> = vec![0].into();
+        let mut scoring = Vec::new();
+        let mut replay = if replay { Some(String::new()) } else { None };
+        let mut board = VecDeque::with_capacity(self.points + 1);
+        board.push_back(0);
         let mut current_player = 0;
-        let mut current_idx: usize = 0;
         let mut current_marble = 1;
 
-        let five_percent = self.points / 20;
+        let mut reported_twentieths = 0;
 
         while current_marble <= self.points {
-            if current_marble % five_percent == 0 {
-                println!("{}%...", current_marble * 100/ self.points);
+            let twentieths = current_marble * 20 / self.points;
+            if twentieths > reported_twentieths {
+                reported_twentieths = twentieths;
+                on_progress(twentieths * 5);
             }
 
-            // print!("{}> {:3} | ", current_player + 1, current_idx);
-            // for x in &board {
-            //     print!("{:3} ", x);
-            // }
-            // println!();
+            if let Some(replay) = &mut replay {
+                write_board(replay, current_player, &board);
+            }
 
-            if current_marble % 23 == 0 {
-                let remove_idx = (current_idx).checked_sub(7).unwrap_or(current_idx + board.len() - 7);
-                let previous_marble = board.remove(remove_idx).expect("No previous marble");
+            if current_marble % rules.scoring_modulus == 0 && !board.is_empty() {
+                let len = board.len();
+                board.rotate_right(rules.removal_distance % len);
+                let previous_marble = board.pop_back().expect("No previous marble");
 
                 scores[current_player] += current_marble + previous_marble;
+                scoring.push(Scoring {
+                    player: current_player,
+                    marble: current_marble,
+                    removed: previous_marble,
+                });
 
-                current_idx = remove_idx;
+                // The marble clockwise of the removed one becomes current
+                if !board.is_empty() {
+                    board.rotate_left(1);
+                }
             } else {
-                let insert_idx = (current_idx + 1) % board.len() + 1;
-
-                board.insert(insert_idx , current_marble);
-
-                current_idx = insert_idx;
+                let len = board.len();
+                if len > 0 {
+                    board.rotate_left(rules.insertion_offset % len);
+                }
+                board.push_back(current_marble);
             }
 
             current_marble += 1;
             current_player = (current_player + 1) % self.players;
         }
 
-        scores.into_iter().max().expect("No players")
+        Game { scores, scoring, replay }
+    }
+}
+
+fn write_board(out: &mut String, player: usize, board: &VecDeque<usize>) {
+    let start = board.iter().position(|&marble| marble == 0).unwrap_or(0);
+    let current_idx = (board.len() + board.len() - start).saturating_sub(1) % board.len().max(1);
+
+    write!(out, "{}> {:3} | ", player + 1, current_idx).expect("Writing to a string failed");
+    for x in board.iter().cycle().skip(start).take(board.len()) {
+        write!(out, "{:3} ", x).expect("Writing to a string failed");
     }
+    out.push('\n');
 }
 
 fn config() -> Result<Config> {
@@ -81,30 +231,132 @@ fn config() -> Result<Config> {
 
 #[test]
 fn test_0() {
-    assert_eq!(Config { players: 9, points: 25 }.run_game(), 32);
+    assert_eq!(Config { players: 9, points: 25 }.run_game().winner().1, 32);
 }
 
 #[test]
 fn test_1() {
-    assert_eq!(Config { players: 10, points: 1618 }.run_game(), 8317);
+    assert_eq!(Config { players: 10, points: 1618 }.run_game().winner().1, 8317);
 }
 
 #[test]
 fn test_2() {
-    assert_eq!(Config { players: 13, points: 7999 }.run_game(), 146373);
+    assert_eq!(Config { players: 13, points: 7999 }.run_game().winner().1, 146373);
 }
 
 #[test]
 fn test_3() {
-    assert_eq!(Config { players: 17, points: 1104 }.run_game(), 2764);
+    assert_eq!(Config { players: 17, points: 1104 }.run_game().winner().1, 2764);
 }
 
 #[test]
 fn test_4() {
-    assert_eq!(Config { players: 21, points: 6111 }.run_game(), 54718);
+    assert_eq!(Config { players: 21, points: 6111 }.run_game().winner().1, 54718);
 }
 
 #[test]
 fn test_5() {
-    assert_eq!(Config { players: 30, points: 5807 }.run_game(), 37305);
+    assert_eq!(Config { players: 30, points: 5807 }.run_game().winner().1, 37305);
+}
+
+#[test]
+fn puzzle_answers() {
+    let mut config = config().unwrap();
+    assert_eq!(config.run_game().winner().1, 412959);
+
+    config.points *= 100;
+    assert_eq!(config.run_game().winner().1, 3333662986);
+}
+
+#[test]
+fn game_details() {
+    let game = Config { players: 9, points: 25 }.play(&Rules::default(), true, |_| {});
+
+    assert_eq!(game.scores, [0, 0, 0, 0, 32, 0, 0, 0, 0]);
+    assert_eq!(game.winner(), (4, 32));
+    assert_eq!(game.scoring, [Scoring { player: 4, marble: 23, removed: 9 }]);
+
+    let replay = game.replay.unwrap();
+    let lines: Vec<_> = replay.lines().collect();
+    assert_eq!(lines.len(), 25);
+    assert_eq!(lines[0], "1>   0 |   0 ");
+    assert_eq!(lines[1], "2>   1 |   0   1 ");
+    assert_eq!(lines[4], "5>   1 |   0   4   2   1   3 ");
+    assert_eq!(
+        lines[22],
+        "5>  13 |   0  16   8  17   4  18   9  19   2  20  10  21   5  22  11   1  \
+         12   6  13   3  14   7  15 "
+    );
+    assert_eq!(
+        lines[23],
+        "6>   6 |   0  16   8  17   4  18  19   2  20  10  21   5  22  11   1  \
+         12   6  13   3  14   7  15 "
+    );
+
+    assert_eq!(Config { players: 9, points: 25 }.run_game().replay, None);
+}
+
+#[test]
+fn winner_ties_go_to_the_first_player() {
+    let game = Game { scores: vec![3, 7, 7, 1], scoring: vec![], replay: None };
+    assert_eq!(game.winner(), (1, 7));
+}
+
+#[test]
+fn progress() {
+    let mut reported = Vec::new();
+    Config { players: 9, points: 25 }.play(&Rules::default(), false, |percent| {
+        reported.push(percent)
+    });
+    assert_eq!(reported, (1..=20).map(|n| n * 5).collect::<Vec<_>>());
+
+    let mut reported = Vec::new();
+    Config { players: 9, points: 7 }.play(&Rules::default(), false, |percent| {
+        reported.push(percent)
+    });
+    assert_eq!(reported, [10, 25, 40, 55, 70, 85, 100]);
+}
+
+#[test]
+fn small_games() {
+    assert_eq!(Config { players: 2, points: 0 }.run_game().scores, [0, 0]);
+    assert_eq!(Config { players: 9, points: 19 }.run_game().winner().1, 0);
+    assert_eq!(Config { players: 9, points: 23 }.run_game().winner().1, 32);
+}
+
+#[test]
+fn placing_next_to_the_current_marble() {
+    let rules = Rules { insertion_offset: 0, ..Rules::default() };
+    let game = Config { players: 1, points: 23 }.play(&rules, true, |_| {});
+
+    // Marbles end up in order, so marble 15 is 7 counter-clockwise of 22
+    assert_eq!(game.scores, [23 + 15]);
+    let replay = game.replay.unwrap();
+    assert_eq!(replay.lines().nth(3), Some("1>   3 |   0   1   2   3 "));
+}
+
+#[test]
+fn removing_the_current_marble() {
+    let rules = Rules { scoring_modulus: 5, removal_distance: 0, insertion_offset: 1 };
+    let game = Config { players: 2, points: 10 }.play(&rules, false, |_| {});
+
+    // Marble 5 takes marble 4, then marble 10 takes marble 9
+    assert_eq!(
+        game.scoring,
+        [
+            Scoring { player: 0, marble: 5, removed: 4 },
+            Scoring { player: 1, marble: 10, removed: 9 },
+        ]
+    );
+    assert_eq!(game.scores, [9, 19]);
+}
+
+#[test]
+fn scoring_every_marble() {
+    let rules = Rules { scoring_modulus: 1, ..Rules::default() };
+    let game = Config { players: 2, points: 3 }.play(&rules, true, |_| {});
+
+    // Marble 1 takes marble 0, leaving nothing for marble 2 to take
+    assert_eq!(game.scores, [1 + 3 + 2, 0]);
+    assert_eq!(game.replay.unwrap(), "1>   0 |   0 \n2>   0 | \n1>   0 |   2 \n");
 }